@@ -0,0 +1,442 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+const TARGET: &str = "block";
+
+/// Directory listing all block devices and partitions.
+const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+
+/// Number of bytes read from the beginning of a device, enough for all supported superblocks.
+const PROBE_SIZE: usize = 4096;
+
+/// Offset of ext2/3/4 superblock.
+const EXT_SUPERBLOCK: usize = 1024;
+
+/// Tag identifying a block device in mount source, e.g. `LABEL=data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    /// Filesystem label.
+    Label,
+
+    /// Filesystem UUID.
+    Uuid,
+
+    /// GPT partition GUID or MBR disk signature with partition number.
+    PartUuid,
+}
+
+impl Tag {
+    const ALL: [Self; 3] = [Self::Label, Self::Uuid, Self::PartUuid];
+
+    /// Split mount source like `UUID=...` into tag and value.
+    pub fn parse(source: &str) -> Option<(Self, &str)> {
+        Self::ALL.into_iter().find_map(|tag| {
+            source
+                .strip_prefix(tag.as_str())
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(|value| (tag, value))
+        })
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Label => "LABEL",
+            Self::Uuid => "UUID",
+            Self::PartUuid => "PARTUUID",
+        }
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Filesystem found on a block device.
+#[derive(Debug, Clone)]
+pub struct Filesystem {
+    /// Filesystem type as accepted by `mount(2)`.
+    pub fstype: &'static str,
+
+    pub uuid: Option<String>,
+
+    pub label: Option<String>,
+}
+
+fn u16_le(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_le(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_le(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Format 16 bytes as UUID in big-endian byte order.
+fn format_uuid(bytes: &[u8]) -> String {
+    let hex = |range: std::ops::Range<usize>| -> String {
+        bytes[range].iter().map(|b| format!("{:02x}", b)).collect()
+    };
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex(0..4),
+        hex(4..6),
+        hex(6..8),
+        hex(8..10),
+        hex(10..16)
+    )
+}
+
+/// Format 16 bytes as GUID, where first three fields are little-endian.
+fn format_guid(bytes: &[u8]) -> String {
+    let mut uuid = [0; 16];
+    uuid.copy_from_slice(&bytes[..16]);
+    uuid[0..4].reverse();
+    uuid[4..6].reverse();
+    uuid[6..8].reverse();
+    format_uuid(&uuid)
+}
+
+/// Parse label padded with zeros or spaces. Empty label is `None`.
+fn parse_label(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    let label = String::from_utf8_lossy(&bytes[..end])
+        .trim_end()
+        .to_string();
+    (!label.is_empty()).then_some(label)
+}
+
+fn probe_ext(buf: &[u8]) -> Option<Filesystem> {
+    let sb = &buf[EXT_SUPERBLOCK..];
+    if u16_le(sb, 0x38) != 0xef53 {
+        return None;
+    }
+    let compat = u32_le(sb, 0x5c);
+    let incompat = u32_le(sb, 0x60);
+    // extents, 64bit or flex_bg require ext4, journal requires ext3
+    let fstype = if incompat & (0x40 | 0x80 | 0x200) != 0 {
+        "ext4"
+    } else if compat & 0x4 != 0 {
+        "ext3"
+    } else {
+        "ext2"
+    };
+    Some(Filesystem {
+        fstype,
+        uuid: Some(format_uuid(&sb[0x68..0x78])),
+        label: parse_label(&sb[0x78..0x88]),
+    })
+}
+
+fn probe_xfs(buf: &[u8]) -> Option<Filesystem> {
+    if &buf[0..4] != b"XFSB" {
+        return None;
+    }
+    Some(Filesystem {
+        fstype: "xfs",
+        uuid: Some(format_uuid(&buf[32..48])),
+        label: parse_label(&buf[108..120]),
+    })
+}
+
+fn probe_squashfs(buf: &[u8]) -> Option<Filesystem> {
+    if &buf[0..4] != b"hsqs" {
+        return None;
+    }
+    Some(Filesystem {
+        fstype: "squashfs",
+        uuid: None,
+        label: None,
+    })
+}
+
+fn probe_vfat(buf: &[u8]) -> Option<Filesystem> {
+    if buf[510..512] != [0x55, 0xaa] {
+        return None;
+    }
+    // FAT32 and FAT12/16 have extended boot record at different offsets.
+    let (serial, label) = if &buf[82..87] == b"FAT32" {
+        (67, 71)
+    } else if &buf[54..57] == b"FAT" {
+        (39, 43)
+    } else {
+        return None;
+    };
+    let serial = u32_le(buf, serial);
+    Some(Filesystem {
+        fstype: "vfat",
+        uuid: Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff)),
+        label: parse_label(&buf[label..label + 11]).filter(|label| label != "NO NAME"),
+    })
+}
+
+fn probe_swap(buf: &[u8]) -> Option<Filesystem> {
+    // Signature is located at the end of the first page, assuming 4 KiB pages.
+    if &buf[PROBE_SIZE - 10..] != b"SWAPSPACE2" {
+        return None;
+    }
+    Some(Filesystem {
+        fstype: "swap",
+        uuid: Some(format_uuid(&buf[1036..1052])),
+        label: parse_label(&buf[1052..1068]),
+    })
+}
+
+fn read_probe_area(device: &Path) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; PROBE_SIZE];
+    File::open(device)?.read_exact_at(&mut buf, 0)?;
+    Ok(buf)
+}
+
+/// Probe filesystem on `device`.
+///
+/// Returns `None` if the device has no filesystem of supported type
+/// (ext2/3/4, xfs, squashfs, vfat or swap).
+pub fn probe(device: &Path) -> io::Result<Option<Filesystem>> {
+    let buf = read_probe_area(device)?;
+    Ok(probe_ext(&buf)
+        .or_else(|| probe_xfs(&buf))
+        .or_else(|| probe_squashfs(&buf))
+        .or_else(|| probe_vfat(&buf))
+        .or_else(|| probe_swap(&buf)))
+}
+
+/// Check whether the beginning of `device` is all zeros, i.e. it contains no data of any kind,
+/// including filesystems not recognized by [`probe`].
+pub fn is_blank(device: &Path) -> io::Result<bool> {
+    Ok(read_probe_area(device)?.iter().all(|byte| *byte == 0))
+}
+
+/// Check whether `device` starts with MBR signature, i.e. contains MBR or GPT partition table.
+pub fn has_partition_table(device: &Path) -> io::Result<bool> {
+    let buf = read_probe_area(device)?;
+    Ok(buf[510..512] == [0x55, 0xaa])
+}
+
+/// Partition UUID of partition `name` read from partition table of its disk.
+///
+/// For GPT this is unique partition GUID, for MBR it is disk signature with partition number
+/// like `0a1b2c3d-01`. Returns `None` if `name` is not a partition.
+fn partition_uuid(name: &str) -> io::Result<Option<String>> {
+    let sys_path = Path::new(SYS_CLASS_BLOCK).join(name);
+    let Some(number) = fs::read_to_string(sys_path.join("partition"))
+        .ok()
+        .and_then(|number| number.trim().parse::<u64>().ok())
+    else {
+        return Ok(None);
+    };
+    // Partition directory is located inside directory of its disk.
+    let disk_path = fs::canonicalize(&sys_path)?;
+    let Some(disk_name) = disk_path.parent().and_then(Path::file_name) else {
+        return Ok(None);
+    };
+    let sector_size = fs::read_to_string(
+        Path::new(SYS_CLASS_BLOCK)
+            .join(disk_name)
+            .join("queue/logical_block_size"),
+    )
+    .ok()
+    .and_then(|size| size.trim().parse::<u64>().ok())
+    .unwrap_or(512);
+
+    let disk = File::open(Path::new("/dev").join(disk_name))?;
+    let mut mbr = [0; 512];
+    disk.read_exact_at(&mut mbr, 0)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(None);
+    }
+
+    // Type of the first partition in protective MBR of GPT disk.
+    if mbr[446 + 4] == 0xee {
+        let mut header = [0; 92];
+        disk.read_exact_at(&mut header, sector_size)?;
+        if &header[0..8] != b"EFI PART" {
+            return Ok(None);
+        }
+        let entries_lba = u64_le(&header, 72);
+        let entries_count = u32_le(&header, 80) as u64;
+        let entry_size = u32_le(&header, 84) as u64;
+        if number == 0 || number > entries_count {
+            return Ok(None);
+        }
+        let mut entry = [0; 32];
+        disk.read_exact_at(
+            &mut entry,
+            entries_lba * sector_size + (number - 1) * entry_size,
+        )?;
+        return Ok(Some(format_guid(&entry[16..32])));
+    }
+
+    Ok(Some(format!("{:08x}-{:02x}", u32_le(&mbr, 440), number)))
+}
+
+/// Find block device with `tag` equal to `value`.
+///
+/// UUIDs are compared case-insensitively, labels are compared exactly.
+pub fn find(tag: Tag, value: &str) -> io::Result<Option<PathBuf>> {
+    let mut names: Vec<String> = fs::read_dir(SYS_CLASS_BLOCK)?
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+
+    for name in names {
+        let device = Path::new("/dev").join(&name);
+        let found = match tag {
+            Tag::Label | Tag::Uuid => probe(&device).map(|fs| {
+                let fs = fs?;
+                log::debug!(
+                    target: TARGET,
+                    "{}: {} UUID={} LABEL={}",
+                    device.display(),
+                    fs.fstype,
+                    fs.uuid.as_deref().unwrap_or(""),
+                    fs.label.as_deref().unwrap_or("")
+                );
+                if tag == Tag::Label {
+                    fs.label
+                } else {
+                    fs.uuid
+                }
+            }),
+            Tag::PartUuid => partition_uuid(&name),
+        };
+        match found {
+            Ok(Some(found)) => {
+                let matches = match tag {
+                    Tag::Label => found == value,
+                    Tag::Uuid | Tag::PartUuid => found.eq_ignore_ascii_case(value),
+                };
+                if matches {
+                    return Ok(Some(device));
+                }
+            }
+            Ok(None) => {}
+            Err(err) => log::debug!(target: TARGET, "probing {}: {}", device.display(), err),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe_buf(buf: &[u8]) -> Option<Filesystem> {
+        probe_ext(buf)
+            .or_else(|| probe_xfs(buf))
+            .or_else(|| probe_squashfs(buf))
+            .or_else(|| probe_vfat(buf))
+            .or_else(|| probe_swap(buf))
+    }
+
+    const UUID: [u8; 16] = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd,
+        0xef,
+    ];
+
+    #[test]
+    fn blank() {
+        assert!(probe_buf(&[0; PROBE_SIZE]).is_none());
+    }
+
+    #[test]
+    fn ext() {
+        let mut buf = vec![0; PROBE_SIZE];
+        let sb = EXT_SUPERBLOCK;
+        buf[sb + 0x38..sb + 0x3a].copy_from_slice(&0xef53u16.to_le_bytes());
+        buf[sb + 0x68..sb + 0x78].copy_from_slice(&UUID);
+        buf[sb + 0x78..sb + 0x7c].copy_from_slice(b"data");
+        let fs = probe_buf(&buf).unwrap();
+        assert_eq!(fs.fstype, "ext2");
+        assert_eq!(
+            fs.uuid.as_deref(),
+            Some("01234567-89ab-cdef-0123-456789abcdef")
+        );
+        assert_eq!(fs.label.as_deref(), Some("data"));
+
+        buf[sb + 0x5c] = 0x4;
+        assert_eq!(probe_buf(&buf).unwrap().fstype, "ext3");
+        buf[sb + 0x60] = 0x40;
+        assert_eq!(probe_buf(&buf).unwrap().fstype, "ext4");
+    }
+
+    #[test]
+    fn xfs() {
+        let mut buf = vec![0; PROBE_SIZE];
+        buf[0..4].copy_from_slice(b"XFSB");
+        buf[32..48].copy_from_slice(&UUID);
+        let fs = probe_buf(&buf).unwrap();
+        assert_eq!(fs.fstype, "xfs");
+        assert_eq!(
+            fs.uuid.as_deref(),
+            Some("01234567-89ab-cdef-0123-456789abcdef")
+        );
+        assert_eq!(fs.label, None);
+    }
+
+    #[test]
+    fn squashfs() {
+        let mut buf = vec![0; PROBE_SIZE];
+        buf[0..4].copy_from_slice(b"hsqs");
+        assert_eq!(probe_buf(&buf).unwrap().fstype, "squashfs");
+    }
+
+    #[test]
+    fn vfat() {
+        let mut buf = vec![0; PROBE_SIZE];
+        buf[510..512].copy_from_slice(&[0x55, 0xaa]);
+        buf[82..87].copy_from_slice(b"FAT32");
+        buf[67..71].copy_from_slice(&0x1234abcdu32.to_le_bytes());
+        buf[71..82].copy_from_slice(b"BOOT       ");
+        let fs = probe_buf(&buf).unwrap();
+        assert_eq!(fs.fstype, "vfat");
+        assert_eq!(fs.uuid.as_deref(), Some("1234-ABCD"));
+        assert_eq!(fs.label.as_deref(), Some("BOOT"));
+
+        buf[71..82].copy_from_slice(b"NO NAME    ");
+        assert_eq!(probe_buf(&buf).unwrap().label, None);
+    }
+
+    #[test]
+    fn partition_table_is_not_vfat() {
+        let mut buf = vec![0; PROBE_SIZE];
+        buf[510..512].copy_from_slice(&[0x55, 0xaa]);
+        assert!(probe_buf(&buf).is_none());
+    }
+
+    #[test]
+    fn swap() {
+        let mut buf = vec![0; PROBE_SIZE];
+        buf[PROBE_SIZE - 10..].copy_from_slice(b"SWAPSPACE2");
+        buf[1036..1052].copy_from_slice(&UUID);
+        let fs = probe_buf(&buf).unwrap();
+        assert_eq!(fs.fstype, "swap");
+        assert_eq!(
+            fs.uuid.as_deref(),
+            Some("01234567-89ab-cdef-0123-456789abcdef")
+        );
+    }
+
+    #[test]
+    fn guid() {
+        assert_eq!(format_guid(&UUID), "67452301-ab89-efcd-0123-456789abcdef");
+    }
+
+    #[test]
+    fn tag() {
+        assert_eq!(Tag::parse("LABEL=data"), Some((Tag::Label, "data")));
+        assert_eq!(
+            Tag::parse("PARTUUID=0a1b-01"),
+            Some((Tag::PartUuid, "0a1b-01"))
+        );
+        assert_eq!(Tag::parse("/dev/vda"), None);
+    }
+}
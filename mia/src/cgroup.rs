@@ -0,0 +1,109 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::mia_config::CgroupConfig;
+
+const TARGET: &str = "cgroup";
+
+/// Mount point of cgroup v2 hierarchy.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Name of the cgroup created for the main command.
+const WORKLOAD_CGROUP: &str = "workload";
+
+fn write(path: &Path, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+    log::debug!(target: TARGET, "{} <- {}", path.display(), value);
+    fs::write(path, value).map_err(|err| format!("writing {}: {}", path.display(), err).into())
+}
+
+/// Cgroup v2 with resource limits.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    /// Whether memory controller is enabled.
+    memory: bool,
+}
+
+impl Cgroup {
+    /// Create cgroup for the main command and apply resource limits to it.
+    pub fn create(config: &CgroupConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = Path::new(CGROUP_ROOT);
+        let path = root.join(WORKLOAD_CGROUP);
+        log::info!(target: TARGET, "creating {}", path.display());
+
+        // Enable only controllers required by configured limits.
+        let limits = [
+            ("memory", "memory.max", &config.memory_max),
+            ("memory", "memory.high", &config.memory_high),
+            ("cpu", "cpu.max", &config.cpu_max),
+            ("pids", "pids.max", &config.pids_max),
+        ];
+        let mut controllers: Vec<&str> = Vec::new();
+        for (controller, _, value) in &limits {
+            if value.is_some() && !controllers.contains(controller) {
+                controllers.push(*controller);
+            }
+        }
+        let memory = controllers.contains(&"memory");
+        for controller in controllers {
+            write(
+                &root.join("cgroup.subtree_control"),
+                &format!("+{}", controller),
+            )?;
+        }
+
+        if !path.exists() {
+            fs::create_dir(&path)?;
+        }
+        for (_, file, value) in limits {
+            if let Some(value) = value {
+                log::info!(target: TARGET, "{}: {}", file, value);
+                write(&path.join(file), &value.to_string())?;
+            }
+        }
+        Ok(Self { path, memory })
+    }
+
+    /// Move the child process into this cgroup before executing `command`.
+    pub fn add_on_exec(
+        &self,
+        command: &mut process::Command,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let procs: OwnedFd = OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))?
+            .into();
+        // SAFETY: `write` is async-signal-safe, no memory is allocated.
+        unsafe {
+            command.pre_exec(move || {
+                // Writing 0 moves the writing process itself.
+                if libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) != 1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Number of processes in this cgroup killed by OOM killer.
+    ///
+    /// Returns `None` if memory controller is not enabled.
+    pub fn oom_kills(&self) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        if !self.memory {
+            return Ok(None);
+        }
+        let events = fs::read_to_string(self.path.join("memory.events"))?;
+        let oom_kills = events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .ok_or("no oom_kill in memory.events")?
+            .trim()
+            .parse()?;
+        Ok(Some(oom_kills))
+    }
+}
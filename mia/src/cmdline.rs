@@ -0,0 +1,210 @@
+use std::fs;
+
+use once_cell::sync::OnceCell;
+
+const TARGET: &str = "cmdline";
+
+const PROC_CMDLINE: &str = "/proc/cmdline";
+
+/// Prefix of MIA parameters.
+const PREFIX: &str = "mia.";
+
+/// Prefix of environment variable parameters after [`PREFIX`].
+const ENV_PREFIX: &str = "env.";
+
+/// MIA parameters from kernel command line and MIA arguments.
+///
+/// Arguments take precedence over kernel command line.
+#[derive(Debug, Default)]
+pub struct Cmdline {
+    /// `mia.config=`: path of the first runtime configuration file.
+    pub config: Option<String>,
+
+    /// `mia.log=`: log filter like `debug` or `info,mount=debug`, overrides `MIA_LOG`.
+    pub log: Option<String>,
+
+    /// `mia.debug_shell`: start shell on console before shutdown.
+    pub debug_shell: bool,
+
+    /// `mia.timeout=`: global deadline in seconds, overrides `timeout` of MIA configuration.
+    pub timeout: Option<u64>,
+
+    /// `mia.env.KEY=VALUE`: environment variables overriding ones of runtime configuration.
+    pub env: Vec<(String, String)>,
+}
+
+static CMDLINE: OnceCell<Cmdline> = OnceCell::new();
+
+/// Split kernel command line into parameters.
+///
+/// Double quotes allow spaces in values, e.g. `mia.env.ARGS="-v -x"`, and are removed.
+fn split(cmdline: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut param = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !param.is_empty() {
+                    params.push(std::mem::take(&mut param));
+                }
+            }
+            c => param.push(c),
+        }
+    }
+    if !param.is_empty() {
+        params.push(param);
+    }
+    params
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "y" | "yes" | "true" | "on" => Some(true),
+        "0" | "n" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl Cmdline {
+    /// Apply parameter `param` if it is a MIA parameter.
+    fn parse_param(&mut self, param: &str) {
+        let Some(rest) = param.strip_prefix(PREFIX) else {
+            return;
+        };
+        let (name, value) = match rest.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (rest, None),
+        };
+        if let (Some(key), Some(value)) = (
+            name.strip_prefix(ENV_PREFIX).filter(|key| !key.is_empty()),
+            value,
+        ) {
+            self.env.retain(|(other, _)| other != key);
+            self.env.push((key.to_string(), value.to_string()));
+            log::info!(target: TARGET, "{}", param);
+            return;
+        }
+
+        // Like the kernel, treat dashes and underscores in names the same.
+        let valid = match (name.replace('-', "_").as_str(), value) {
+            ("config", Some(value)) => {
+                self.config = Some(value.to_string());
+                true
+            }
+            ("log", Some(value)) => {
+                self.log = Some(value.to_string());
+                true
+            }
+            ("debug_shell", None) => {
+                self.debug_shell = true;
+                true
+            }
+            ("debug_shell", Some(value)) => parse_bool(value)
+                .map(|value| self.debug_shell = value)
+                .is_some(),
+            ("timeout", Some(value)) => value
+                .parse()
+                .map(|value| self.timeout = Some(value))
+                .is_ok(),
+            _ => false,
+        };
+        if valid {
+            log::info!(target: TARGET, "{}", param);
+        } else {
+            log::warn!(target: TARGET, "ignoring invalid parameter: {}", param);
+        }
+    }
+
+    fn load() -> Self {
+        let mut cmdline = Self::default();
+        match fs::read_to_string(PROC_CMDLINE) {
+            Ok(content) => {
+                for param in split(&content) {
+                    cmdline.parse_param(&param);
+                }
+            }
+            Err(err) => log::warn!(target: TARGET, "reading {}: {}", PROC_CMDLINE, err),
+        }
+        for arg in std::env::args().skip(1) {
+            cmdline.parse_param(&arg);
+        }
+        cmdline
+    }
+}
+
+/// MIA parameters, loaded on first use.
+///
+/// Kernel command line is read from `/proc`, so this should be first called after default
+/// mounts.
+pub fn get() -> &'static Cmdline {
+    CMDLINE.get_or_init(Cmdline::load)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_whitespace() {
+        assert_eq!(
+            split("console=ttyS0  mia.log=debug\tquiet\n"),
+            ["console=ttyS0", "mia.log=debug", "quiet"]
+        );
+        assert!(split("  \n").is_empty());
+    }
+
+    #[test]
+    fn split_quotes() {
+        assert_eq!(
+            split(r#"mia.env.ARGS="-v -x" "a b"c quiet"#),
+            ["mia.env.ARGS=-v -x", "a bc", "quiet"]
+        );
+        // Unterminated quote extends to the end.
+        assert_eq!(split(r#"a "b c"#), ["a", "b c"]);
+    }
+
+    #[test]
+    fn params() {
+        let mut cmdline = Cmdline::default();
+        for param in [
+            "root=/dev/vda",
+            "mia.config=/etc/mia.yaml",
+            "mia.debug-shell",
+            "mia.timeout=60",
+            "mia.env.KEY=a=b",
+            "mia.env.KEY=c",
+            "mia.env.EMPTY=",
+        ] {
+            cmdline.parse_param(param);
+        }
+        assert_eq!(cmdline.config.as_deref(), Some("/etc/mia.yaml"));
+        assert!(cmdline.debug_shell);
+        assert_eq!(cmdline.timeout, Some(60));
+        assert_eq!(
+            cmdline.env,
+            [
+                ("KEY".to_string(), "c".to_string()),
+                ("EMPTY".to_string(), String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_params() {
+        let mut cmdline = Cmdline::default();
+        for param in [
+            "mia.timeout=soon",
+            "mia.debug_shell=maybe",
+            "mia.env.KEY",
+            "mia.config",
+        ] {
+            cmdline.parse_param(param);
+        }
+        assert_eq!(cmdline.timeout, None);
+        assert!(!cmdline.debug_shell);
+        assert!(cmdline.env.is_empty());
+        assert_eq!(cmdline.config, None);
+    }
+}
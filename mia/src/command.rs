@@ -1,18 +1,289 @@
+use std::fmt;
+use std::os::unix::process::CommandExt;
 use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+
+use crate::cgroup::Cgroup;
+use crate::mia_config::{RestartConfig, RestartPolicy};
+use crate::rlimit::Rlimits;
+use crate::user::Credentials;
+use crate::{reaper, services, signals};
 
 const TARGET: &str = "command";
 
+/// Default time given to the command to exit after forwarding a termination signal to it.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Global deadline for all commands.
+static DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Set global deadline for all commands.
+///
+/// Commands running at the deadline are terminated, no commands are started after it.
+pub fn set_deadline(deadline: Instant) {
+    *DEADLINE.lock().unwrap() = Some(deadline);
+}
+
+/// Grace period configured for all commands, used when terminating remaining processes.
+static GRACE_PERIOD: Mutex<Duration> = Mutex::new(DEFAULT_GRACE_PERIOD);
+
+/// Set grace period used when terminating remaining processes before exit.
+pub fn set_grace_period(grace_period: Duration) {
+    *GRACE_PERIOD.lock().unwrap() = grace_period;
+}
+
+/// Configured grace period, [`DEFAULT_GRACE_PERIOD`] if not set.
+pub fn grace_period() -> Duration {
+    *GRACE_PERIOD.lock().unwrap()
+}
+
+/// Deadline set with [`set_deadline`], if any.
+pub fn global_deadline() -> Option<Instant> {
+    *DEADLINE.lock().unwrap()
+}
+
+fn deadline_reached() -> bool {
+    global_deadline().is_some_and(|deadline| deadline <= Instant::now())
+}
+
+/// Exit status of a finished command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Command exited with the code.
+    Exited(i32),
+
+    /// Command was terminated by the signal.
+    Signaled(Signal),
+
+    /// Command was terminated because it exceeded its timeout or the global deadline.
+    TimedOut,
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        *self == Self::Exited(0)
+    }
+
+    /// Convert status of a terminated process.
+    ///
+    /// Returns `None` if the process didn't terminate.
+    pub fn from_wait_status(status: WaitStatus) -> Option<Self> {
+        match status {
+            WaitStatus::Exited(_, code) => Some(Self::Exited(code)),
+            WaitStatus::Signaled(_, signal, _) => Some(Self::Signaled(signal)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited(code) => write!(f, "exited with code {}", code),
+            Self::Signaled(signal) => write!(f, "killed by signal {}", signal),
+            Self::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// Error returned by [`Command::run`] when the command timed out.
+#[derive(Debug)]
+pub struct TimeoutError {
+    command: String,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "command `{}` timed out", &self.command)
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+impl RestartConfig {
+    /// Returns the reason not to restart a command, whose `attempt` finished with `status`.
+    ///
+    /// `None` means that the command should be restarted.
+    pub fn stop_reason(&self, status: ExitStatus, attempt: u32) -> Option<&'static str> {
+        if self.policy == RestartPolicy::Never {
+            Some("restart policy is never")
+        } else if self.policy == RestartPolicy::OnFailure && status.success() {
+            Some("command succeeded")
+        } else if self.max_attempts != 0 && attempt >= self.max_attempts {
+            Some("maximum attempts reached")
+        } else if signals::termination_requested() {
+            Some("termination requested")
+        } else if deadline_reached() {
+            Some("global deadline reached")
+        } else {
+            None
+        }
+    }
+}
+
 pub struct Command {
     command: String,
     args: Vec<String>,
+    grace_period: Duration,
+    restart: RestartConfig,
+    timeout: Option<Duration>,
+    rlimits: Rlimits,
+    cgroup: Option<Cgroup>,
+    credentials: Option<Credentials>,
 }
 
 impl Command {
     pub fn new(command: String, args: Vec<String>) -> Self {
-        Self { command, args }
+        Self {
+            command,
+            args,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            restart: RestartConfig::default(),
+            timeout: None,
+            rlimits: Rlimits::default(),
+            cgroup: None,
+            credentials: None,
+        }
     }
 
-    pub fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Set time given to the command to exit after forwarding a termination signal to it.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Set restart configuration of the command.
+    pub fn restart(mut self, restart: RestartConfig) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Set maximum execution time of the command.
+    ///
+    /// With restart policy, timeout applies to every attempt separately.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set resource limits of the command.
+    pub fn rlimits(mut self, rlimits: Rlimits) -> Self {
+        self.rlimits = rlimits;
+        self
+    }
+
+    /// Set cgroup to run the command in.
+    pub fn cgroup(mut self, cgroup: Cgroup) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    /// Cgroup the command runs in.
+    pub fn get_cgroup(&self) -> Option<&Cgroup> {
+        self.cgroup.as_ref()
+    }
+
+    /// Set user credentials and privileges to run the command with.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Run the command, restarting it according to restart policy, and return its last
+    /// exit status.
+    pub fn status(&self) -> Result<ExitStatus, Box<dyn std::error::Error>> {
+        if self.restart.policy == RestartPolicy::Never {
+            return self.status_once();
+        }
+
+        let max_attempts = self.restart.max_attempts;
+        let mut backoff = Duration::from_secs(self.restart.backoff);
+        let mut attempt = 1;
+        loop {
+            if max_attempts == 0 {
+                log::info!(target: TARGET, "`{}` attempt {}", &self.command, attempt);
+            } else {
+                log::info!(
+                    target: TARGET,
+                    "`{}` attempt {}/{}",
+                    &self.command,
+                    attempt,
+                    max_attempts
+                );
+            }
+            let status = self.status_once()?;
+
+            if let Some(reason) = self.restart.stop_reason(status, attempt) {
+                log::info!(
+                    target: TARGET,
+                    "`{}` {}, not restarting: {}",
+                    &self.command,
+                    status,
+                    reason
+                );
+                return Ok(status);
+            }
+
+            log::warn!(
+                target: TARGET,
+                "`{}` {}, restarting in {:?}",
+                &self.command,
+                status,
+                backoff
+            );
+            if !sleep(backoff)? {
+                log::info!(
+                    target: TARGET,
+                    "`{}` not restarted: termination requested",
+                    &self.command
+                );
+                return Ok(status);
+            }
+            backoff = (backoff * 2).min(Duration::from_secs(self.restart.max_backoff));
+            attempt += 1;
+        }
+    }
+
+    /// Run the command once and return its exit status.
+    fn status_once(&self) -> Result<ExitStatus, Box<dyn std::error::Error>> {
+        signals::poll()?;
+        if signals::termination_requested() {
+            return Err(Box::from(format!(
+                "command `{}` not started: termination requested",
+                &self.command
+            )));
+        }
+
+        if deadline_reached() {
+            log::warn!(
+                target: TARGET,
+                "`{}` not started: global deadline reached",
+                &self.command
+            );
+            return Ok(ExitStatus::TimedOut);
+        }
+
+        let pid = self.spawn()?;
+        let (status, timed_out) = self.wait(pid)?;
+        if timed_out {
+            return Ok(ExitStatus::TimedOut);
+        }
+        ExitStatus::from_wait_status(status).ok_or_else(|| {
+            Box::from(format!(
+                "command `{}` failed with unexpected status: {:?}",
+                &self.command, status
+            ))
+        })
+    }
+
+    /// Spawn the command in its own process group without waiting for it.
+    pub fn spawn(&self) -> Result<Pid, Box<dyn std::error::Error>> {
         let mut command = process::Command::new(self.command.as_str());
         log::info!(
             target: TARGET,
@@ -26,19 +297,128 @@ impl Command {
         for arg in &self.args {
             command.arg(arg);
         }
-        let mut child = command.spawn()?;
-        let status = child.wait()?; // Reap the child process to avoid zombie processes
-        if !status.success() {
-            return Err(Box::from(format!(
-                "command `{}` failed with code: {}",
-                &self.command,
-                status
-                    .code()
-                    .as_ref()
-                    .map(ToString::to_string)
-                    .unwrap_or("<unknown>".to_string())
-            )));
+        // Run the command in its own process group, so that signals can be forwarded to all
+        // of its processes.
+        command.process_group(0);
+        signals::unblock_on_exec(&mut command);
+        // Limits must be set before dropping privileges, raising hard limits requires root.
+        self.rlimits.apply_on_exec(&mut command);
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.add_on_exec(&mut command)?;
+        }
+        if let Some(credentials) = &self.credentials {
+            credentials.apply_on_exec(&mut command);
+        }
+        let child = command.spawn()?;
+        Ok(Pid::from_raw(child.id() as i32))
+    }
+
+    /// Run the command and fail if it didn't succeed.
+    pub fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.status()? {
+            ExitStatus::Exited(0) => Ok(()),
+            ExitStatus::TimedOut => Err(Box::new(TimeoutError {
+                command: self.command.clone(),
+            })),
+            status => Err(Box::from(format!("command `{}` {}", &self.command, status))),
+        }
+    }
+
+    /// Wait for the command process to exit.
+    ///
+    /// Termination signals received by MIA are forwarded to the process group of the command.
+    /// When the command exceeds its timeout or the global deadline, it is sent `SIGTERM`.
+    /// If it doesn't exit within grace period, the whole group is killed with `SIGKILL`.
+    ///
+    /// Returns exit status of the process and whether it timed out.
+    fn wait(&self, pid: Pid) -> Result<(WaitStatus, bool), Box<dyn std::error::Error>> {
+        let command_deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let deadline = [command_deadline, global_deadline()]
+            .into_iter()
+            .flatten()
+            .min();
+        let mut timed_out = false;
+        let mut kill_deadline: Option<Instant> = None;
+        loop {
+            // Reap the child process (and any orphans) to avoid zombie processes
+            if let Some(status) = reaper::reap(pid)? {
+                return Ok((status, timed_out));
+            }
+
+            let now = Instant::now();
+            if !timed_out && deadline.is_some_and(|deadline| deadline <= now) {
+                log::warn!(target: TARGET, "`{}` timed out, terminating", &self.command);
+                send_signal(pid, Signal::SIGTERM);
+                timed_out = true;
+                kill_deadline.get_or_insert(now + self.grace_period);
+            }
+            if kill_deadline.is_some_and(|deadline| deadline <= now) {
+                log::warn!(
+                    target: TARGET,
+                    "`{}` did not exit within {:?}, killing",
+                    &self.command,
+                    self.grace_period
+                );
+                send_signal(pid, Signal::SIGKILL);
+                kill_deadline = None;
+            }
+
+            let deadline = if timed_out { None } else { deadline };
+            match signals::wait(timeout([deadline, kill_deadline, services::next_restart()]))? {
+                Some(Signal::SIGCHLD) | None => {}
+                Some(signal) => {
+                    log::info!(
+                        target: TARGET,
+                        "forwarding {} to `{}` (pid {})",
+                        signal,
+                        &self.command,
+                        pid
+                    );
+                    send_signal(pid, signal);
+                    kill_deadline.get_or_insert(Instant::now() + self.grace_period);
+                }
+            }
+            services::restart_due();
+        }
+    }
+}
+
+/// Time left until the earliest of `deadlines`.
+fn timeout<const N: usize>(deadlines: [Option<Instant>; N]) -> Option<Duration> {
+    deadlines
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Sleep for `duration` unless termination is requested.
+///
+/// Sleep ends earlier if global deadline is reached.
+/// Returns `false` if sleep was interrupted by a termination signal.
+fn sleep(duration: Duration) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut deadline = Instant::now() + duration;
+    if let Some(global_deadline) = global_deadline() {
+        deadline = deadline.min(global_deadline);
+    }
+    loop {
+        if Instant::now() >= deadline {
+            return Ok(true);
+        }
+        // Reap exited children, so that services can be restarted meanwhile
+        reaper::reap_orphans()?;
+        match signals::wait(timeout([Some(deadline), services::next_restart()]))? {
+            Some(Signal::SIGCHLD) | None => {}
+            Some(_) => return Ok(false),
         }
-        Ok(())
+        services::restart_due();
+    }
+}
+
+/// Send `signal` to the process group `pgid`.
+pub fn send_signal(pgid: Pid, signal: Signal) {
+    match killpg(pgid, signal) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(err) => log::error!(target: TARGET, "sending {}: {}", signal, err),
     }
 }
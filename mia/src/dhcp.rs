@@ -0,0 +1,576 @@
+use std::fs::{self, File};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{command, signals};
+
+const TARGET: &str = "dhcp";
+
+/// Time to wait for a lease if not configured.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time before the first retransmission, doubled with every following one.
+const INITIAL_RETRANSMIT: Duration = Duration::from_secs(1);
+
+const MAX_RETRANSMIT: Duration = Duration::from_secs(8);
+
+/// Longest wait for a reply before checking for termination signals.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+
+/// Ask server to broadcast replies, because the client can't receive unicast before it has
+/// an address.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Offset of options in DHCP message, after fixed BOOTP fields and magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+
+/// Minimum size of BOOTP message, some servers drop shorter ones.
+const MIN_MESSAGE_SIZE: usize = 300;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_DOMAIN_NAME: u8 = 15;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// Address configuration received from DHCP server.
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub address: Ipv4Addr,
+
+    pub prefix_len: u8,
+
+    /// Default gateway.
+    pub gateway: Option<Ipv4Addr>,
+
+    /// DNS servers.
+    pub dns: Vec<Ipv4Addr>,
+
+    /// Domain name used as DNS search domain.
+    pub domain: Option<String>,
+
+    /// Lease time in seconds.
+    pub lease_time: Option<u32>,
+}
+
+/// DHCP message received from server.
+struct Reply {
+    message_type: u8,
+    address: Ipv4Addr,
+    options: Vec<(u8, Vec<u8>)>,
+}
+
+impl Reply {
+    fn parse(buf: &[u8], xid: u32) -> Option<Self> {
+        if buf.len() < OPTIONS_OFFSET
+            || buf[0] != BOOTREPLY
+            || buf[4..8] != xid.to_be_bytes()
+            || buf[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut options = Vec::new();
+        let mut rest = &buf[OPTIONS_OFFSET..];
+        while let Some((&code, tail)) = rest.split_first() {
+            match code {
+                OPTION_PAD => rest = tail,
+                OPTION_END => break,
+                _ => {
+                    let (&len, tail) = tail.split_first()?;
+                    let data = tail.get(..len as usize)?;
+                    options.push((code, data.to_vec()));
+                    rest = &tail[len as usize..];
+                }
+            }
+        }
+        let message_type = options
+            .iter()
+            .find(|(code, _)| *code == OPTION_MESSAGE_TYPE)
+            .and_then(|(_, data)| data.first().copied())?;
+        Some(Self {
+            message_type,
+            address: Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]),
+            options,
+        })
+    }
+
+    fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(option, _)| *option == code)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Addresses in option `code`.
+    fn addresses(&self, code: u8) -> Vec<Ipv4Addr> {
+        self.option(code)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|chunk| Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]))
+            .collect()
+    }
+
+    fn lease(&self) -> Lease {
+        let prefix_len = self
+            .addresses(OPTION_SUBNET_MASK)
+            .first()
+            .map_or(32, |mask| u32::from(*mask).count_ones() as u8);
+        Lease {
+            address: self.address,
+            prefix_len,
+            gateway: self.addresses(OPTION_ROUTER).first().copied(),
+            dns: self.addresses(OPTION_DNS),
+            domain: self
+                .option(OPTION_DOMAIN_NAME)
+                .map(|domain| {
+                    String::from_utf8_lossy(domain)
+                        .trim_end_matches('\0')
+                        .to_string()
+                })
+                .filter(|domain| !domain.is_empty()),
+            lease_time: self
+                .option(OPTION_LEASE_TIME)
+                .and_then(|time| time.try_into().ok())
+                .map(u32::from_be_bytes),
+        }
+    }
+}
+
+/// Read hardware address of interface `name`.
+fn hardware_address(name: &str) -> Result<[u8; 6], Box<dyn std::error::Error>> {
+    let path = Path::new("/sys/class/net").join(name).join("address");
+    let address =
+        fs::read_to_string(&path).map_err(|err| format!("reading {}: {}", path.display(), err))?;
+    let bytes = address
+        .trim()
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    bytes
+        .try_into()
+        .map_err(|_| Box::from(format!("{} is not an Ethernet interface", name)))
+}
+
+/// Build client message of `message_type` with additional `options`.
+fn message(xid: u32, mac: &[u8; 6], message_type: u8, options: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut buf = vec![0; OPTIONS_OFFSET];
+    buf[0] = BOOTREQUEST;
+    buf[1] = HTYPE_ETHERNET;
+    buf[2] = mac.len() as u8;
+    buf[4..8].copy_from_slice(&xid.to_be_bytes());
+    buf[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    buf[28..34].copy_from_slice(mac);
+    buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+    let parameters: &[u8] = &[
+        OPTION_SUBNET_MASK,
+        OPTION_ROUTER,
+        OPTION_DNS,
+        OPTION_DOMAIN_NAME,
+        OPTION_LEASE_TIME,
+    ];
+    for (code, data) in [(OPTION_MESSAGE_TYPE, &[message_type][..])]
+        .iter()
+        .chain(options)
+        .chain(&[(OPTION_PARAMETERS, parameters)])
+    {
+        buf.push(*code);
+        buf.push(data.len() as u8);
+        buf.extend(*data);
+    }
+    buf.push(OPTION_END);
+    buf.resize(buf.len().max(MIN_MESSAGE_SIZE), 0);
+    buf
+}
+
+/// Open socket sending and receiving DHCP messages on interface `name` only.
+fn open_socket(name: &str) -> Result<UdpSocket, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, CLIENT_PORT))?;
+    // SAFETY: name is valid for reads of its length.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr().cast(),
+            name.len() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(Box::from(format!(
+            "binding to {}: {}",
+            name,
+            io::Error::last_os_error()
+        )));
+    }
+    socket.set_broadcast(true)?;
+    Ok(socket)
+}
+
+/// Broadcast `message` until a reply of one of `expected` types arrives.
+///
+/// Returns `None` if there is no reply until `deadline`. Fails if termination is requested.
+fn exchange(
+    socket: &UdpSocket,
+    message: &[u8],
+    xid: u32,
+    expected: &[u8],
+    deadline: Instant,
+) -> Result<Option<Reply>, Box<dyn std::error::Error>> {
+    let server = SocketAddrV4::new(Ipv4Addr::BROADCAST, SERVER_PORT);
+    let mut retransmit = INITIAL_RETRANSMIT;
+    let mut buf = [0; 1500];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        socket.send_to(message, server)?;
+        let resend = deadline.min(now + retransmit);
+        retransmit = (retransmit * 2).min(MAX_RETRANSMIT);
+        loop {
+            signals::poll()?;
+            if signals::termination_requested() {
+                return Err(Box::from(
+                    "DHCP exchange interrupted: termination requested",
+                ));
+            }
+            let now = Instant::now();
+            if now >= resend {
+                break;
+            }
+            socket.set_read_timeout(Some((resend - now).min(SIGNAL_POLL_INTERVAL)))?;
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            match Reply::parse(&buf[..len], xid) {
+                Some(reply) if expected.contains(&reply.message_type) => return Ok(Some(reply)),
+                _ => log::debug!(target: TARGET, "ignoring unexpected message"),
+            }
+        }
+    }
+}
+
+fn random_xid() -> io::Result<u32> {
+    let mut xid = [0; 4];
+    File::open("/dev/urandom")?.read_exact_at(&mut xid, 0)?;
+    Ok(u32::from_ne_bytes(xid))
+}
+
+/// Obtain DHCPv4 lease for interface `name`, which must be up.
+///
+/// Waiting ends after `timeout`, at the global deadline or when termination is requested.
+/// The lease is not renewed, so it should outlive the VM.
+pub fn request(name: &str, timeout: Duration) -> Result<Lease, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let deadline =
+        command::global_deadline().map_or(start + timeout, |global| global.min(start + timeout));
+    let mac = hardware_address(name)?;
+    let socket = open_socket(name)?;
+    log::info!(target: TARGET, "{}: requesting lease", name);
+
+    loop {
+        let xid = random_xid()?;
+        let discover = message(xid, &mac, DHCPDISCOVER, &[]);
+        let Some(offer) = exchange(&socket, &discover, xid, &[DHCPOFFER], deadline)? else {
+            break;
+        };
+        let Some(server_id) = offer.option(OPTION_SERVER_ID) else {
+            log::warn!(target: TARGET, "{}: offer without server identifier", name);
+            continue;
+        };
+        log::debug!(target: TARGET, "{}: offered {}", name, offer.address);
+
+        let request = message(
+            xid,
+            &mac,
+            DHCPREQUEST,
+            &[
+                (OPTION_REQUESTED_ADDRESS, &offer.address.octets()),
+                (OPTION_SERVER_ID, server_id),
+            ],
+        );
+        match exchange(&socket, &request, xid, &[DHCPACK, DHCPNAK], deadline)? {
+            Some(reply) if reply.message_type == DHCPACK => {
+                let lease = reply.lease();
+                log::info!(
+                    target: TARGET,
+                    "{}: leased {}/{}",
+                    name,
+                    lease.address,
+                    lease.prefix_len
+                );
+                if let Some(lease_time) = lease.lease_time {
+                    log::info!(target: TARGET, "{}: lease time {}s", name, lease_time);
+                }
+                return Ok(lease);
+            }
+            Some(_) => log::warn!(target: TARGET, "{}: request declined by server", name),
+            None => break,
+        }
+    }
+    Err(Box::from(format!(
+        "no DHCP lease on {} within {}s",
+        name,
+        deadline.saturating_duration_since(start).as_secs_f64()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    const XID: u32 = 0x1234_5678;
+    const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    const ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+    /// Build server reply of `message_type` with `options`, as sent by a DHCP server.
+    fn reply(xid: u32, message_type: u8, options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut buf = vec![0; OPTIONS_OFFSET];
+        buf[0] = BOOTREPLY;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&xid.to_be_bytes());
+        buf[16..20].copy_from_slice(&ADDRESS.octets());
+        buf[28..34].copy_from_slice(&MAC);
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+        buf.extend([OPTION_MESSAGE_TYPE, 1, message_type]);
+        // Padding is allowed between options.
+        buf.push(OPTION_PAD);
+        for (code, data) in options {
+            buf.push(*code);
+            buf.push(data.len() as u8);
+            buf.extend(*data);
+        }
+        buf.push(OPTION_END);
+        buf
+    }
+
+    fn ack(xid: u32) -> Vec<u8> {
+        reply(
+            xid,
+            DHCPACK,
+            &[
+                (OPTION_SUBNET_MASK, &[255, 255, 255, 0]),
+                (OPTION_ROUTER, &SERVER.octets()),
+                (OPTION_DNS, &[10, 0, 2, 3, 1, 1, 1, 1]),
+                (OPTION_LEASE_TIME, &3600u32.to_be_bytes()),
+                (OPTION_SERVER_ID, &SERVER.octets()),
+                (OPTION_DOMAIN_NAME, b"example.com\0"),
+            ],
+        )
+    }
+
+    /// Parse client message by treating it as a reply.
+    fn parse_client_message(message: &[u8], xid: u32) -> Reply {
+        assert_eq!(message[0], BOOTREQUEST);
+        let mut buf = message.to_vec();
+        buf[0] = BOOTREPLY;
+        Reply::parse(&buf, xid).unwrap()
+    }
+
+    #[test]
+    fn discover_message() {
+        let message = message(XID, &MAC, DHCPDISCOVER, &[]);
+        assert!(message.len() >= MIN_MESSAGE_SIZE);
+        assert_eq!(&message[..3], &[BOOTREQUEST, HTYPE_ETHERNET, 6]);
+        assert_eq!(message[4..8], XID.to_be_bytes());
+        assert_eq!(message[10..12], FLAG_BROADCAST.to_be_bytes());
+        assert_eq!(message[28..34], MAC);
+        assert_eq!(message[236..240], MAGIC_COOKIE);
+
+        let discover = parse_client_message(&message, XID);
+        assert_eq!(discover.message_type, DHCPDISCOVER);
+        assert_eq!(discover.address, Ipv4Addr::UNSPECIFIED);
+        let parameters = discover.option(OPTION_PARAMETERS).unwrap();
+        for code in [
+            OPTION_SUBNET_MASK,
+            OPTION_ROUTER,
+            OPTION_DNS,
+            OPTION_LEASE_TIME,
+        ] {
+            assert!(parameters.contains(&code));
+        }
+        assert_eq!(discover.option(OPTION_SERVER_ID), None);
+    }
+
+    #[test]
+    fn request_message() {
+        let message = message(
+            XID,
+            &MAC,
+            DHCPREQUEST,
+            &[
+                (OPTION_REQUESTED_ADDRESS, &ADDRESS.octets()),
+                (OPTION_SERVER_ID, &SERVER.octets()),
+            ],
+        );
+        let request = parse_client_message(&message, XID);
+        assert_eq!(request.message_type, DHCPREQUEST);
+        assert_eq!(
+            request.addresses(OPTION_REQUESTED_ADDRESS),
+            [Ipv4Addr::new(10, 0, 2, 15)]
+        );
+        assert_eq!(request.addresses(OPTION_SERVER_ID), [SERVER]);
+        // Options end with end option followed by padding only.
+        let end = message.iter().rposition(|byte| *byte != 0).unwrap();
+        assert_eq!(message[end], OPTION_END);
+    }
+
+    #[test]
+    fn offer() {
+        let buf = reply(XID, DHCPOFFER, &[(OPTION_SERVER_ID, &SERVER.octets())]);
+        let offer = Reply::parse(&buf, XID).unwrap();
+        assert_eq!(offer.message_type, DHCPOFFER);
+        assert_eq!(offer.address, ADDRESS);
+        assert_eq!(offer.option(OPTION_SERVER_ID), Some(&SERVER.octets()[..]));
+    }
+
+    #[test]
+    fn lease() {
+        let ack = Reply::parse(&ack(XID), XID).unwrap();
+        assert_eq!(ack.message_type, DHCPACK);
+        let lease = ack.lease();
+        assert_eq!(lease.address, ADDRESS);
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(lease.gateway, Some(SERVER));
+        assert_eq!(
+            lease.dns,
+            [Ipv4Addr::new(10, 0, 2, 3), Ipv4Addr::new(1, 1, 1, 1)]
+        );
+        assert_eq!(lease.domain.as_deref(), Some("example.com"));
+        assert_eq!(lease.lease_time, Some(3600));
+    }
+
+    #[test]
+    fn minimal_lease() {
+        let lease = Reply::parse(&reply(XID, DHCPACK, &[]), XID)
+            .unwrap()
+            .lease();
+        assert_eq!(lease.prefix_len, 32);
+        assert_eq!(lease.gateway, None);
+        assert!(lease.dns.is_empty());
+        assert_eq!(lease.domain, None);
+        assert_eq!(lease.lease_time, None);
+    }
+
+    #[test]
+    fn options_after_end_are_ignored() {
+        let mut buf = reply(XID, DHCPACK, &[]);
+        buf.extend([OPTION_ROUTER, 4, 10, 0, 2, 2]);
+        let lease = Reply::parse(&buf, XID).unwrap().lease();
+        assert_eq!(lease.gateway, None);
+    }
+
+    #[test]
+    fn invalid_replies() {
+        let valid = reply(XID, DHCPOFFER, &[]);
+        assert!(Reply::parse(&valid, XID ^ 1).is_none());
+        assert!(Reply::parse(&valid[..OPTIONS_OFFSET - 1], XID).is_none());
+
+        let mut request = valid.clone();
+        request[0] = BOOTREQUEST;
+        assert!(Reply::parse(&request, XID).is_none());
+
+        let mut cookie = valid.clone();
+        cookie[236] = 0;
+        assert!(Reply::parse(&cookie, XID).is_none());
+
+        // Option length beyond the end of message.
+        let mut truncated = valid[..OPTIONS_OFFSET].to_vec();
+        truncated.extend([OPTION_MESSAGE_TYPE, 1, DHCPOFFER, OPTION_DNS, 8, 10, 0]);
+        assert!(Reply::parse(&truncated, XID).is_none());
+
+        let mut no_type = valid[..OPTIONS_OFFSET].to_vec();
+        no_type.push(OPTION_END);
+        assert!(Reply::parse(&no_type, XID).is_none());
+    }
+
+    /// Answer DISCOVER and REQUEST on `socket` like a DHCP server.
+    fn serve(socket: UdpSocket) {
+        let client = SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT);
+        let mut buf = [0; 1500];
+        for _ in 0..2 {
+            let len = socket.recv(&mut buf).unwrap();
+            let xid = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+            let message = parse_client_message(&buf[..len], xid);
+            let reply = match message.message_type {
+                DHCPDISCOVER => reply(xid, DHCPOFFER, &[(OPTION_SERVER_ID, &SERVER.octets())]),
+                DHCPREQUEST => {
+                    assert_eq!(message.addresses(OPTION_SERVER_ID), [SERVER]);
+                    ack(xid)
+                }
+                message_type => panic!("unexpected message type {}", message_type),
+            };
+            socket.send_to(&reply, client).unwrap();
+        }
+    }
+
+    /// Obtain lease from fake server on loopback of a new network namespace.
+    #[test]
+    #[ignore = "requires CAP_SYS_ADMIN to create network namespace"]
+    fn request_lease() {
+        // SAFETY: only the namespace of this test thread is changed.
+        assert_eq!(unsafe { libc::unshare(libc::CLONE_NEWNET) }, 0);
+        crate::network::loopback().unwrap();
+
+        let socket =
+            UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, SERVER_PORT)).unwrap();
+        socket.set_broadcast(true).unwrap();
+        // Broadcast replies are only routed with the interface set explicitly.
+        // SAFETY: name is valid for reads of its length.
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                "lo".as_ptr().cast(),
+                2,
+            )
+        };
+        assert_eq!(ret, 0);
+        let server = thread::spawn(move || serve(socket));
+
+        let lease = request("lo", Duration::from_secs(10)).unwrap();
+        server.join().unwrap();
+        assert_eq!(lease.address, ADDRESS);
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(lease.gateway, Some(SERVER));
+    }
+}
@@ -1,9 +1,29 @@
-use env_logger::{Builder, Env, Target};
+use env_logger::{Builder, Env, Logger, Target};
+use once_cell::sync::OnceCell;
 use std::io::Write;
+use std::sync::RwLock;
 
-pub fn setup() {
-    let logger_env = Env::default().filter_or("MIA_LOG", "info");
-    Builder::from_env(logger_env)
+/// Logger with filter which can be replaced after installation, e.g. from kernel command line.
+struct MiaLogger(RwLock<Logger>);
+
+impl log::Log for MiaLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.0.read().unwrap().flush()
+    }
+}
+
+static LOGGER: OnceCell<MiaLogger> = OnceCell::new();
+
+fn build(builder: &mut Builder) -> Logger {
+    builder
         .target(Target::Stdout)
         .format(|buf, record| {
             if record.target().is_empty() {
@@ -18,5 +38,22 @@ pub fn setup() {
                 )
             }
         })
-        .init();
+        .build()
+}
+
+pub fn setup() {
+    let logger_env = Env::default().filter_or("MIA_LOG", "info");
+    let logger = build(&mut Builder::from_env(logger_env));
+    log::set_max_level(logger.filter());
+    let logger = LOGGER.get_or_init(|| MiaLogger(RwLock::new(logger)));
+    log::set_logger(logger).expect("logger is already set");
+}
+
+/// Replace log filter with `filter` like `debug` or `info,mount=debug`.
+pub fn set_filter(filter: &str) {
+    let logger = build(Builder::new().parse_filters(filter));
+    log::set_max_level(logger.filter());
+    if let Some(current) = LOGGER.get() {
+        *current.0.write().unwrap() = logger;
+    }
 }
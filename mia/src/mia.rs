@@ -1,12 +1,34 @@
+use std::time::{Duration, Instant};
+
 use nix::sys::reboot::RebootMode;
 
+use command::{ExitStatus, TimeoutError};
+use status::Phase;
+
+mod block;
+mod cgroup;
+mod cmdline;
 mod command;
+mod dhcp;
 mod logger;
+mod mia_config;
+mod mkfs;
 mod modprobe;
 mod mount;
+mod mount_options;
+mod network;
 mod pre_exit;
 mod qemu;
+mod reaper;
+mod rlimit;
 mod rt_config;
+mod services;
+mod signals;
+mod status;
+mod swap;
+mod switch_root;
+mod sysctl;
+mod user;
 
 const TARGET: &str = "";
 const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
@@ -24,35 +46,104 @@ fn shutdown() -> ! {
 
 const MIA_CONFIG_PATH: &str = "/usr/lib/mia/config.yaml";
 
-fn start() -> Result<(), Box<dyn std::error::Error>> {
+fn start() -> Result<ExitStatus, Box<dyn std::error::Error>> {
+    let start_time = Instant::now();
     logger::setup();
     log::info!(target: TARGET, "MIA version {}", VERSION.unwrap_or("<unknown>"));
 
+    // As PID 1, MIA is responsible for reaping orphaned processes and handling termination signals
+    signals::setup()?;
+
     // Mount default filesystems (including kernel API)
+    status::set_phase(Phase::Mount);
     crate::mount::default_mounts()?;
 
-    let cmd = rt_config::load(MIA_CONFIG_PATH.to_string())?;
+    // Kernel command line is available only after /proc is mounted
+    let cmdline = cmdline::get();
+    if let Some(filter) = &cmdline.log {
+        logger::set_filter(filter);
+    }
+    if let Some(timeout) = cmdline.timeout {
+        log::info!(target: TARGET, "global timeout: {}s", timeout);
+        command::set_deadline(start_time + Duration::from_secs(timeout));
+    }
+
+    // Loopback is not essential unless network is configured, which fails on its own
+    if let Err(err) = network::loopback() {
+        log::warn!(target: TARGET, "loopback is not available: {}", err);
+    }
+
+    let config_path = cmdline
+        .config
+        .clone()
+        .unwrap_or_else(|| MIA_CONFIG_PATH.to_string());
+    let config = rt_config::load(config_path, start_time)?;
+
+    status::set_phase(Phase::Services);
+    services::start(&config.mia.services)?;
 
     log::info!(target: TARGET, "run main process");
-    cmd.run()?;
+    status::set_phase(Phase::Command);
+    let status = config.command.status();
 
-    Ok(())
+    if let Some(cgroup) = config.command.get_cgroup() {
+        match cgroup.oom_kills() {
+            Ok(Some(oom_kills)) => {
+                if oom_kills > 0 {
+                    log::warn!(target: TARGET, "main process OOM kills: {}", oom_kills);
+                }
+                status::set_oom_kills(oom_kills);
+            }
+            Ok(None) => {}
+            Err(err) => log::error!(target: TARGET, "reading OOM kills: {}", err),
+        }
+    }
+
+    // Main command result decides the exit status, services are just stopped.
+    if let Err(err) = services::stop(command::grace_period()) {
+        log::error!(target: TARGET, "stopping services: {}", err);
+    }
+
+    status
 }
 
 // Init process should never return.
 fn main() -> ! {
-    let err = if let Err(e) = start() {
-        log::error!(target: TARGET, "{}", e);
-        true
-    } else {
-        false
+    let (status, error) = match start() {
+        Ok(status) => {
+            if status.success() {
+                log::info!(target: TARGET, "main process {}", status);
+                (Some(status), None)
+            } else {
+                log::error!(target: TARGET, "main process {}", status);
+                (Some(status), Some(format!("main process {}", status)))
+            }
+        }
+        Err(e) => {
+            log::error!(target: TARGET, "{}", e);
+            // Timeout of a boot command is reported the same way as timeout of the main one
+            let status = e
+                .downcast_ref::<TimeoutError>()
+                .map(|_| ExitStatus::TimedOut);
+            (status, Some(e.to_string()))
+        }
     };
 
+    status::write(status, error);
+
+    if cmdline::get().debug_shell {
+        pre_exit::debug_shell();
+    }
+
+    pre_exit::kill_processes(command::grace_period());
+    swap::deactivate_all();
+    mount::unmount_all();
+
     // Sync filesystems before attempting to shutdown.
     nix::unistd::sync();
 
     if qemu::QEMU_EXIT_HANDLER.get().is_some() {
-        qemu::exit(err);
+        qemu::exit(status);
     }
     // If no exit handler was set, perform simple shutdown.
     pre_exit::flush();
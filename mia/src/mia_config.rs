@@ -0,0 +1,720 @@
+//! MIA-specific extension of Gevulot runtime configuration.
+//!
+//! Gevulot runtime configuration doesn't allow unknown fields, so MIA settings are stored under
+//! `mia` key of the runtime configuration file. This key is removed before the rest of the file
+//! is deserialized into [`RuntimeConfig`](gevulot_rs::runtime_config::RuntimeConfig).
+//!
+//! ```yaml
+//! version: 1
+//! command: prover
+//! mia:
+//!   grace-period: 10
+//!   status-path: /output/status.yaml
+//!   timeout: 3600
+//!   command-timeout: 3000
+//!   bootcmd-timeout: 60
+//!   command-rlimits:
+//!     nofile: 65536
+//!     stack: unlimited
+//!     core: { soft: 0, hard: unlimited }
+//!   cgroup:
+//!     memory-max: 4G
+//!     pids-max: 1024
+//!   user:
+//!     uid: 1000
+//!     groups: [44]
+//!     capabilities: [CAP_NET_BIND_SERVICE]
+//!   restart:
+//!     policy: on-failure
+//!     max-attempts: 3
+//!   switch-root:
+//!     source: LABEL=rootfs
+//!     fstype: ext4
+//!     wait-timeout: 10
+//!   overlay:
+//!     options: size=50%
+//!     directories: [/etc, /var]
+//!   mount-wait-timeout: 5
+//!   mounts:
+//!     - source: UUID=0e6e8c88-23e0-42b5-a583-6e61b4531328
+//!       target: /scratch
+//!       fstype: ext4
+//!       options: noatime,nodev,discard
+//!     - source: /input
+//!       target: /workspace/input
+//!       options: rbind,ro,nosuid,nodev,private
+//!     - source: /run/secrets/key.pem
+//!       target: /workspace/key.pem
+//!       options: bind,ro
+//!       create: { mode: "0600", uid: 1000, gid: 1000 }
+//!       wait-timeout: 30
+//!     - source: /dev/vdc
+//!       target: /output
+//!       format:
+//!         fstype: ext4
+//!         label: output
+//!   swap:
+//!     - source: /scratch/swapfile
+//!       size: 8G
+//!   zram:
+//!     size: 4G
+//!     algorithm: zstd
+//!   sysctl:
+//!     vm.overcommit_memory: 1
+//!     kernel.core_pattern: /output/core.%e.%p
+//!     vm.nr_hugepages: { value: 512, required: false }
+//!     net.core.somaxconn: 4096
+//!   network:
+//!     hostname: prover-1
+//!     domain: example.internal
+//!     hosts:
+//!       - address: 10.1.0.10
+//!         names: [db, db.example.internal]
+//!     dns-servers: [10.1.0.53]
+//!     interfaces:
+//!       - name: eth0
+//!         dhcp: true
+//!       - name: eth1
+//!         mtu: 1460
+//!         addresses: [10.1.0.2/24, fd00::2/64]
+//!         routes:
+//!           - destination: 10.2.0.0/16
+//!             gateway: 10.1.0.1
+//!   services:
+//!     - name: metrics
+//!       command: /usr/bin/metrics-exporter
+//!       args: [--port, "9100"]
+//!       restart:
+//!         policy: always
+//!         max-attempts: 0
+//! ```
+//!
+//! When configurations are chained with `follow-config`, values from following configuration
+//! override previously set ones. Lists (e.g. `services`) are concatenated and maps (e.g.
+//! `command-rlimits`) are merged.
+//!
+//! `options` of `mounts`, `switch-root` and `overlay` are `mount(8)`-style option strings like
+//! `rbind,ro,private`. Mounts of the runtime configuration itself (top-level `mounts`) are
+//! different: their `flags` are a numeric `MS_*` bitmask and `data` is passed to the
+//! filesystem as is, so options like `ro` must be given as flags there.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+/// Key of MIA configuration in runtime configuration file.
+const MIA_CONFIG_KEY: &str = "mia";
+
+/// When to restart the main command after it exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restart.
+    #[default]
+    Never,
+
+    /// Restart if the command failed.
+    OnFailure,
+
+    /// Restart regardless of exit status.
+    Always,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_backoff() -> u64 {
+    1
+}
+
+fn default_max_backoff() -> u64 {
+    60
+}
+
+/// Restart configuration of the main command.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RestartConfig {
+    /// Restart policy.
+    pub policy: RestartPolicy,
+
+    /// Maximum number of attempts to run the command, including the first one.
+    ///
+    /// `0` means no limit. Defaults to 3.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Delay in seconds before the first restart. It is doubled with every following restart.
+    ///
+    /// Defaults to 1 second.
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+
+    /// Maximum delay in seconds between restarts.
+    ///
+    /// Defaults to 60 seconds.
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::default(),
+            max_attempts: default_max_attempts(),
+            backoff: default_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+/// Value of a resource limit: a number or `unlimited`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum LimitValue {
+    Number(u64),
+    Keyword(String),
+}
+
+impl fmt::Display for LimitValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(value) => write!(f, "{}", value),
+            Self::Keyword(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Resource limit, see `setrlimit(2)`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RlimitConfig {
+    /// Same soft and hard limit.
+    Value(LimitValue),
+
+    /// Separate soft and hard limits.
+    SoftHard { soft: LimitValue, hard: LimitValue },
+}
+
+/// Value written to a sysctl file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SysctlValue {
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for SysctlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(value) => write!(f, "{}", value),
+            Self::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+fn default_required() -> bool {
+    true
+}
+
+/// Kernel parameter set through `/proc/sys`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SysctlConfig {
+    /// Required parameter.
+    Value(SysctlValue),
+
+    /// Parameter with explicit flag. If it is not required, failure to set it is only logged.
+    Full {
+        value: SysctlValue,
+        #[serde(default = "default_required")]
+        required: bool,
+    },
+}
+
+/// Cgroup limits of the main command.
+///
+/// Values are written to the corresponding cgroup v2 interface files as they are,
+/// e.g. `memory-max: 4G` or `cpu-max: "200000 100000"`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CgroupConfig {
+    /// `memory.max`: memory usage hard limit.
+    pub memory_max: Option<LimitValue>,
+
+    /// `memory.high`: memory usage throttle limit.
+    pub memory_high: Option<LimitValue>,
+
+    /// `cpu.max`: CPU bandwidth limit as `$MAX $PERIOD`.
+    pub cpu_max: Option<LimitValue>,
+
+    /// `pids.max`: maximum number of processes.
+    pub pids_max: Option<LimitValue>,
+}
+
+/// User to run the main command as.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct UserConfig {
+    /// User ID.
+    pub uid: u32,
+
+    /// Group ID. Defaults to `uid`.
+    pub gid: Option<u32>,
+
+    /// Supplementary group IDs.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+
+    /// Capabilities to keep, e.g. `CAP_NET_BIND_SERVICE`.
+    ///
+    /// These are raised as ambient capabilities, so that the command has them even when running
+    /// as non-root user. All other capabilities are dropped from bounding set. If not set,
+    /// bounding set is not changed and non-root user has no capabilities.
+    pub capabilities: Option<Vec<String>>,
+}
+
+/// Background service started before the main command.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServiceConfig {
+    /// Name of the service used in logs.
+    pub name: String,
+
+    /// Program to execute.
+    pub command: String,
+
+    /// Arguments to the command.
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Restart configuration. By default the service is never restarted.
+    pub restart: Option<RestartConfig>,
+}
+
+/// Type of filesystem created by MIA.
+///
+/// Only filesystems which MIA can detect are supported, so that they are never formatted again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FormatType {
+    Ext2,
+    Ext3,
+    Ext4,
+    Xfs,
+    Vfat,
+}
+
+impl FormatType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ext2 => "ext2",
+            Self::Ext3 => "ext3",
+            Self::Ext4 => "ext4",
+            Self::Xfs => "xfs",
+            Self::Vfat => "vfat",
+        }
+    }
+}
+
+impl fmt::Display for FormatType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Filesystem to create on empty device.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FormatConfig {
+    /// Type of the filesystem, `mkfs.<fstype>` is used to create it.
+    pub fstype: FormatType,
+
+    /// Label of the filesystem.
+    pub label: Option<String>,
+}
+
+/// Attributes of mount target created by MIA.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CreateConfig {
+    /// Octal file mode, e.g. `"0750"`.
+    pub mode: Option<String>,
+
+    /// Owner user ID.
+    pub uid: Option<u32>,
+
+    /// Owner group ID.
+    pub gid: Option<u32>,
+}
+
+/// Filesystem mounted by MIA in addition to runtime configuration mounts.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MountConfig {
+    /// Source of the filesystem, can be `LABEL=`, `UUID=` or `PARTUUID=`.
+    ///
+    /// For bind mounts this is the path to bind, for remounts and propagation changes it is
+    /// ignored.
+    pub source: String,
+
+    /// Target path where to mount the filesystem.
+    pub target: String,
+
+    /// Type of the filesystem.
+    pub fstype: Option<String>,
+
+    /// Comma-separated mount options like `ro,nosuid,size=10m`, see `mount(8)`.
+    ///
+    /// Besides filesystem options, these select the operation: `bind`, `rbind`, `remount`,
+    /// `move`, and propagation type: `private`, `shared`, `slave`, `unbindable` and their
+    /// recursive `r` variants.
+    pub options: Option<String>,
+
+    /// Time in seconds to wait for the source device to appear.
+    ///
+    /// Overrides `mount-wait-timeout`.
+    pub wait_timeout: Option<u64>,
+
+    /// Filesystem to create if the source device contains no filesystem.
+    ///
+    /// Source must be a device path or `PARTUUID=`, because empty device has no filesystem
+    /// label or UUID. `fstype` defaults to the type of created filesystem.
+    pub format: Option<FormatConfig>,
+
+    /// Mode and owner of the target if it doesn't exist.
+    ///
+    /// Missing target is created as an empty file if a file is bind mounted and as
+    /// a directory otherwise. Existing targets are not changed.
+    pub create: Option<CreateConfig>,
+}
+
+/// Writable layer stacked over read-only root filesystem.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OverlayConfig {
+    /// Source of upper layer filesystem, e.g. scratch disk. By default tmpfs is used.
+    pub source: Option<String>,
+
+    /// Type of upper layer filesystem. Defaults to `tmpfs` if `source` is not set.
+    pub fstype: Option<String>,
+
+    /// Mount options of upper layer, e.g. `size=50%` for tmpfs.
+    pub options: Option<String>,
+
+    /// Directories to overlay, e.g. `/etc` and `/var`.
+    ///
+    /// If empty, the whole root filesystem is overlaid.
+    #[serde(default)]
+    pub directories: Vec<String>,
+}
+
+/// Swap space on a device or in a file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SwapConfig {
+    /// Swap file or device, which can be given as `LABEL=`, `UUID=` or `PARTUUID=`.
+    ///
+    /// Swap header is created if the device or file contains no swap space, filesystem or
+    /// partition table.
+    pub source: String,
+
+    /// Size of the swap file to create if it doesn't exist, e.g. `4G`.
+    pub size: Option<String>,
+
+    /// Priority from 0 to 32767, higher priority swap is used first.
+    pub priority: Option<u16>,
+
+    /// Discard freed swap pages, useful for SSD and thin-provisioned disks.
+    #[serde(default)]
+    pub discard: bool,
+
+    /// Time in seconds to wait for the device to appear.
+    ///
+    /// Overrides `mount-wait-timeout`.
+    pub wait_timeout: Option<u64>,
+}
+
+/// Compressed swap in memory.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ZramConfig {
+    /// Maximum size of uncompressed data, e.g. `4G`.
+    pub size: String,
+
+    /// Compression algorithm, e.g. `lz4` or `zstd`. Defaults to kernel default.
+    pub algorithm: Option<String>,
+
+    /// Swap priority. Defaults to 100, so that zram is used before other swap.
+    pub priority: Option<u16>,
+}
+
+/// Static route.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RouteConfig {
+    /// Destination network with prefix length like `10.1.0.0/16`, or `default`.
+    pub destination: String,
+
+    /// Gateway address. Without gateway, the destination is reachable directly on the link.
+    pub gateway: Option<String>,
+
+    /// Route metric, lower is preferred.
+    pub metric: Option<u32>,
+}
+
+/// Network interface brought up by MIA.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct InterfaceConfig {
+    /// Name of the interface, e.g. `eth0`.
+    pub name: String,
+
+    /// Maximum transmission unit.
+    pub mtu: Option<u32>,
+
+    /// Static IPv4 or IPv6 addresses with prefix length like `10.0.0.2/24`.
+    #[serde(default)]
+    pub addresses: Vec<String>,
+
+    /// Obtain IPv4 address, default gateway and DNS servers with DHCP.
+    ///
+    /// Domain name received from DHCP is added to DNS search domains. The lease is not
+    /// renewed: the address is removed when the lease time ends, so the server should grant
+    /// leases longer than the VM runs.
+    #[serde(default)]
+    pub dhcp: bool,
+
+    /// Time in seconds to wait for DHCP lease. Defaults to 30 seconds.
+    pub dhcp_timeout: Option<u64>,
+
+    /// Routes via this interface, added after addresses.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+}
+
+/// Entry of `/etc/hosts`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HostConfig {
+    /// IPv4 or IPv6 address.
+    pub address: String,
+
+    /// Host names resolving to the address.
+    pub names: Vec<String>,
+}
+
+/// Network configuration.
+///
+/// Loopback interface is always brought up. Generated files replace existing ones in `/etc`.
+/// If `/etc` is read-only, writable layer on tmpfs is stacked over it.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// Host name of the VM.
+    ///
+    /// If set, `/etc/hosts` is generated with localhost and this host name.
+    pub hostname: Option<String>,
+
+    /// Domain name, used for fully qualified host name in `/etc/hosts`.
+    pub domain: Option<String>,
+
+    /// Additional entries of `/etc/hosts`.
+    ///
+    /// If set, `/etc/hosts` is generated even without `hostname`.
+    #[serde(default)]
+    pub hosts: Vec<HostConfig>,
+
+    /// DNS servers written to `/etc/resolv.conf` before ones received from DHCP.
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+
+    /// DNS search domains.
+    #[serde(default)]
+    pub dns_search: Vec<String>,
+
+    /// Interfaces to configure in order.
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceConfig>,
+}
+
+/// Root filesystem to switch to when MIA runs from initramfs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SwitchRootConfig {
+    /// Device containing the new root filesystem.
+    pub source: String,
+
+    /// Type of the new root filesystem.
+    pub fstype: Option<String>,
+
+    /// Comma-separated mount options like `ro,nosuid,size=10m`, see `mount(8)`.
+    pub options: Option<String>,
+
+    /// Time in seconds to wait for the device to appear.
+    pub wait_timeout: Option<u64>,
+
+    /// Program in the new root to execute as PID 1, e.g. `/usr/lib/mia/mia`.
+    ///
+    /// If not set, MIA continues with `follow-config` of the current file, which is looked up
+    /// in the new root.
+    pub init: Option<String>,
+}
+
+/// MIA configuration.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MiaConfig {
+    /// Time in seconds given to the running command to exit after forwarding a termination
+    /// signal to it. After that the command is killed with `SIGKILL`.
+    pub grace_period: Option<u64>,
+
+    /// Path to write machine-readable status record to when MIA finishes.
+    ///
+    /// This can be a file on an output mount or a device like a dedicated serial port.
+    /// QEMU debug exit can only report exit codes up to 63, so the exact exit code of the main
+    /// command should be read from here.
+    pub status_path: Option<String>,
+
+    /// Restart configuration of the main command.
+    pub restart: Option<RestartConfig>,
+
+    /// Global deadline in seconds since MIA start.
+    ///
+    /// Commands still running at the deadline are terminated and no commands are started
+    /// after it.
+    pub timeout: Option<u64>,
+
+    /// Maximum execution time of the main command in seconds.
+    ///
+    /// With restart policy, it applies to every attempt separately.
+    pub command_timeout: Option<u64>,
+
+    /// Maximum execution time of each boot command in seconds.
+    pub bootcmd_timeout: Option<u64>,
+
+    /// Resource limits of the main command.
+    ///
+    /// Keys are resource names like `nofile` or `RLIMIT_NOFILE`.
+    #[serde(default)]
+    pub command_rlimits: BTreeMap<String, RlimitConfig>,
+
+    /// Resource limits of boot commands.
+    #[serde(default)]
+    pub bootcmd_rlimits: BTreeMap<String, RlimitConfig>,
+
+    /// Cgroup limits of the main command.
+    ///
+    /// If set, the main command runs in a dedicated cgroup.
+    pub cgroup: Option<CgroupConfig>,
+
+    /// User to run the main command as.
+    ///
+    /// If not set, the main command runs as root with full capabilities.
+    pub user: Option<UserConfig>,
+
+    /// Long-running services started before the main command.
+    ///
+    /// Services are stopped in reverse order after the main command exits.
+    #[serde(default)]
+    pub services: Vec<ServiceConfig>,
+
+    /// Writable overlay over root filesystem, which is remounted read-only.
+    ///
+    /// Applied before mounts of the file it is set in, so it should be set in the first
+    /// configuration file. This setting is not merged with following configurations.
+    pub overlay: Option<OverlayConfig>,
+
+    /// Default time in seconds to wait for source devices of mounts to appear.
+    ///
+    /// If not set, mounts fail immediately if the device is missing.
+    pub mount_wait_timeout: Option<u64>,
+
+    /// Filesystems to mount after runtime configuration mounts of the file they are set in.
+    ///
+    /// This setting is not merged with following configurations.
+    #[serde(default)]
+    pub mounts: Vec<MountConfig>,
+
+    /// Swap spaces activated after loading kernel modules of the file they are set in.
+    ///
+    /// This setting is not merged with following configurations.
+    #[serde(default)]
+    pub swap: Vec<SwapConfig>,
+
+    /// Zram swap device set up after loading kernel modules of the file it is set in.
+    ///
+    /// `zram` module has to be built into the kernel or listed in `kernel-modules`. This
+    /// setting is not merged with following configurations.
+    pub zram: Option<ZramConfig>,
+
+    /// Kernel parameters set after loading kernel modules of the file they are set in.
+    ///
+    /// Keys are parameter names like `vm.overcommit_memory` or paths relative to `/proc/sys`
+    /// like `net/ipv4/conf/eth0.100/forwarding`. This setting is not merged with following
+    /// configurations.
+    #[serde(default)]
+    pub sysctl: BTreeMap<String, SysctlConfig>,
+
+    /// Network configured after loading kernel modules of the file it is set in.
+    ///
+    /// This setting is not merged with following configurations.
+    pub network: Option<NetworkConfig>,
+
+    /// Root filesystem to switch to after processing the file it is set in.
+    ///
+    /// This setting is not merged with following configurations.
+    pub switch_root: Option<SwitchRootConfig>,
+}
+
+impl MiaConfig {
+    /// Extract MIA configuration from runtime configuration file `value`.
+    ///
+    /// `mia` key is removed from `value`.
+    pub fn extract(value: &mut Value) -> Result<Self, serde_yaml::Error> {
+        match value
+            .as_mapping_mut()
+            .and_then(|mapping| mapping.remove(MIA_CONFIG_KEY))
+        {
+            Some(config) => serde_yaml::from_value(config),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Override values of this configuration with ones set in `other`.
+    pub fn merge(&mut self, other: Self) {
+        if other.grace_period.is_some() {
+            self.grace_period = other.grace_period;
+        }
+        if other.status_path.is_some() {
+            self.status_path = other.status_path;
+        }
+        if other.restart.is_some() {
+            self.restart = other.restart;
+        }
+        if other.timeout.is_some() {
+            self.timeout = other.timeout;
+        }
+        if other.command_timeout.is_some() {
+            self.command_timeout = other.command_timeout;
+        }
+        if other.bootcmd_timeout.is_some() {
+            self.bootcmd_timeout = other.bootcmd_timeout;
+        }
+        if other.cgroup.is_some() {
+            self.cgroup = other.cgroup;
+        }
+        if other.user.is_some() {
+            self.user = other.user;
+        }
+        if other.mount_wait_timeout.is_some() {
+            self.mount_wait_timeout = other.mount_wait_timeout;
+        }
+        self.command_rlimits.extend(other.command_rlimits);
+        self.bootcmd_rlimits.extend(other.bootcmd_rlimits);
+        self.services.extend(other.services);
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+use crate::block;
+use crate::command::Command;
+use crate::mia_config::{FormatConfig, FormatType};
+
+const TARGET: &str = "mkfs";
+
+/// Directories searched for `mkfs.<fstype>`, starting with helpers bundled with MIA.
+const MKFS_SEARCH_PATH: &[&str] = &["/usr/lib/mia", "/usr/sbin", "/sbin", "/usr/bin", "/bin"];
+
+fn find_mkfs(fstype: FormatType) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let name = format!("mkfs.{}", fstype);
+    MKFS_SEARCH_PATH
+        .iter()
+        .map(|dir| Path::new(dir).join(&name))
+        .find(|path| path.exists())
+        .ok_or_else(|| Box::from(format!("{} not found", name)))
+}
+
+/// Create filesystem on `device` if it is blank.
+///
+/// Devices with any data at the beginning, including partition tables and filesystems not
+/// recognized by [`block::probe`], are never touched.
+pub fn format_if_empty(
+    device: &Path,
+    config: &FormatConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(fs) = block::probe(device)? {
+        log::debug!(target: TARGET, "{} already contains {}", device.display(), fs.fstype);
+        return Ok(());
+    }
+    if block::has_partition_table(device)? {
+        log::warn!(
+            target: TARGET,
+            "{} contains partition table, not formatting",
+            device.display()
+        );
+        return Ok(());
+    }
+    if !block::is_blank(device)? {
+        log::warn!(
+            target: TARGET,
+            "{} contains unrecognized data, not formatting",
+            device.display()
+        );
+        return Ok(());
+    }
+
+    log::info!(
+        target: TARGET,
+        "creating {} on {}",
+        config.fstype,
+        device.display()
+    );
+    let mkfs = find_mkfs(config.fstype)?;
+    let mut args = Vec::new();
+    if let Some(label) = &config.label {
+        // mkfs.vfat uses -L for a different purpose.
+        let flag = if config.fstype == FormatType::Vfat {
+            "-n"
+        } else {
+            "-L"
+        };
+        args.extend([flag.to_string(), label.clone()]);
+    }
+    args.push(device.to_string_lossy().into_owned());
+    Command::new(mkfs.to_string_lossy().into_owned(), args).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn unrecognized_data_is_not_formatted() {
+        let path = std::env::temp_dir().join(format!("mia-mkfs-test-{}", std::process::id()));
+        // LUKS header, not recognized by probe.
+        let mut content = vec![0; 8192];
+        content[..6].copy_from_slice(b"LUKS\xba\xbe");
+        fs::write(&path, &content).unwrap();
+
+        let config = FormatConfig {
+            fstype: FormatType::Ext4,
+            label: None,
+        };
+        let result = format_if_empty(&path, &config);
+        let after = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(after, content);
+    }
+}
@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 use std::process;
 
+use crate::signals;
+
 const TARGET: &str = "modprobe";
 
 const DEFAULT_MODPROBE_PATH: &str = "/usr/lib/mia/modprobe";
@@ -24,9 +26,10 @@ impl Modprobe {
     /// Load kernel module.
     pub fn load(&self, module_name: &str) -> Result<(), Box<dyn std::error::Error>> {
         log::info!(target: TARGET, "loading {}", module_name);
-        let mut child = process::Command::new(self.exec_path.as_os_str())
-            .arg(module_name)
-            .spawn()?;
+        let mut command = process::Command::new(self.exec_path.as_os_str());
+        command.arg(module_name);
+        signals::unblock_on_exec(&mut command);
+        let mut child = command.spawn()?;
         child.wait()?;
         Ok(())
     }
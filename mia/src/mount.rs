@@ -1,12 +1,39 @@
-use std::path::PathBuf;
-use std::{fmt, fs};
+use std::fs::{OpenOptions, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fmt, fs, thread};
 
 use gevulot_rs::runtime_config::Mount as RuntimeMount;
 
+use nix::errno::Errno;
 pub use nix::mount::MsFlags;
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{chown, Gid, Uid};
+
+use crate::block::{self, Tag};
+use crate::mia_config::{CreateConfig, FormatConfig, MountConfig, OverlayConfig};
+use crate::mkfs;
+use crate::mount_options;
 
 const TARGET: &str = "mount";
 
+/// Interval between checks whether source device appeared.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Filesystem mounted by MIA.
+struct Mounted {
+    target: PathBuf,
+
+    /// Whether this is one of [`default_mounts`], which are left mounted before exit.
+    default: bool,
+}
+
+/// Filesystems mounted by MIA in order of mounting.
+static MOUNTED: Mutex<Vec<Mounted>> = Mutex::new(Vec::new());
+
 /// Mount description structure.
 #[derive(Debug, Clone)]
 pub struct Mount {
@@ -22,6 +49,9 @@ pub struct Mount {
     /// Mount flags.
     pub flags: MsFlags,
 
+    /// Propagation type to set after mounting, `MS_REC` to set it in the whole subtree.
+    pub propagation: MsFlags,
+
     /// Mount options.
     ///
     /// Interpreted by filesystem. See `mount(8)` for available options.
@@ -29,6 +59,15 @@ pub struct Mount {
 
     /// If set to `false` mount failure will not interrupt the execution.
     pub required: bool,
+
+    /// Time to wait for source device to appear.
+    pub wait_timeout: Option<Duration>,
+
+    /// Filesystem to create if source device is empty.
+    pub format: Option<FormatConfig>,
+
+    /// Mode and owner of the target if it has to be created.
+    pub create: Option<CreateConfig>,
 }
 
 impl fmt::Display for Mount {
@@ -45,24 +84,300 @@ impl fmt::Display for Mount {
     }
 }
 
+/// Find device of `source`, resolving `LABEL=`, `UUID=` and `PARTUUID=` into device path.
+///
+/// Returns `None` if the source is a device which doesn't exist (yet).
+/// Sources which are not devices are returned unchanged.
+fn find_source(source: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match Tag::parse(source) {
+        Some((tag, value)) => Ok(block::find(tag, value)?.map(|device| {
+            log::info!(target: TARGET, "{} -> {}", source, device.display());
+            device.to_string_lossy().into_owned()
+        })),
+        None if source.starts_with("/dev/") => {
+            Ok(Path::new(source).exists().then(|| source.to_string()))
+        }
+        None => Ok(Some(source.to_string())),
+    }
+}
+
+/// Resolve `source`, waiting up to `wait_timeout` for its device to appear.
+pub fn resolve_source(
+    source: &str,
+    wait_timeout: Option<Duration>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let deadline = Instant::now() + wait_timeout.unwrap_or_default();
+    let mut waiting = false;
+    loop {
+        if let Some(resolved) = find_source(source)? {
+            return Ok(resolved);
+        }
+        if Instant::now() >= deadline {
+            return Err(match wait_timeout {
+                Some(timeout) => format!(
+                    "device {} did not appear within {}s",
+                    source,
+                    timeout.as_secs_f64()
+                ),
+                None => format!("device {} not found", source),
+            }
+            .into());
+        }
+        if !waiting {
+            log::info!(target: TARGET, "waiting for {}", source);
+            waiting = true;
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// Flags changing propagation type of a mount.
+const PROPAGATION_FLAGS: MsFlags = MsFlags::MS_PRIVATE
+    .union(MsFlags::MS_SHARED)
+    .union(MsFlags::MS_SLAVE)
+    .union(MsFlags::MS_UNBINDABLE);
+
+/// Flags which are applied to a bind mount by remounting it.
+const BIND_REMOUNT_FLAGS: MsFlags = MsFlags::MS_RDONLY
+    .union(MsFlags::MS_NOSUID)
+    .union(MsFlags::MS_NODEV)
+    .union(MsFlags::MS_NOEXEC)
+    .union(MsFlags::MS_NOATIME)
+    .union(MsFlags::MS_NODIRATIME)
+    .union(MsFlags::MS_RELATIME)
+    .union(MsFlags::MS_STRICTATIME);
+
+/// Flags controlling access time updates.
+const ATIME_FLAGS: MsFlags = MsFlags::MS_NOATIME
+    .union(MsFlags::MS_RELATIME)
+    .union(MsFlags::MS_STRICTATIME);
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// Decode octal escapes like `\040` used in mountinfo for special characters.
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (byte, escaped) {
+            (b'\\', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// All mount points in order of mounting.
+fn mount_points() -> io::Result<Vec<PathBuf>> {
+    let mountinfo = fs::read_to_string(MOUNTINFO_PATH)?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|mount_point| PathBuf::from(unescape(mount_point)))
+        .collect())
+}
+
+/// Mount points below `target` in order of mounting.
+fn submounts(target: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(mount_points()?
+        .into_iter()
+        .filter(|mount_point| mount_point != target && mount_point.starts_with(target))
+        .collect())
+}
+
+/// Whether a filesystem is mounted at `target`.
+pub fn is_mount_point(target: &Path) -> bool {
+    mount_points()
+        .map(|mount_points| mount_points.iter().any(|mount_point| mount_point == target))
+        .unwrap_or(false)
+}
+
+/// Per-mount flags currently set on mount at `target`.
+fn current_flags(target: &Path) -> Result<MsFlags, Box<dyn std::error::Error>> {
+    let flags = statvfs(target)?.flags();
+    let mapping = [
+        (FsFlags::ST_RDONLY, MsFlags::MS_RDONLY),
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ];
+    Ok(mapping
+        .into_iter()
+        .filter(|(fs_flag, _)| flags.contains(*fs_flag))
+        .fold(MsFlags::empty(), |acc, (_, ms_flag)| acc | ms_flag))
+}
+
+/// Remount bind mount at `target` with `flags` added to its current flags.
+///
+/// Remount replaces all per-mount flags, so current ones are preserved explicitly.
+fn remount_bind(target: &Path, flags: MsFlags) -> Result<(), Box<dyn std::error::Error>> {
+    let mut current = current_flags(target)?;
+    if flags.intersects(ATIME_FLAGS) {
+        current.remove(ATIME_FLAGS);
+    }
+    mount_syscall(
+        None,
+        target,
+        None,
+        MsFlags::MS_REMOUNT | MsFlags::MS_BIND | current | flags,
+        None,
+    )
+}
+
+fn mount_syscall(
+    source: Option<&str>,
+    target: &Path,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    options: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::debug!(target: TARGET, "mount({:?}, {}, {:?}, {:?}, {:?})", source, target.display(), fstype, flags, options);
+    nix::mount::mount(source, target, fstype, flags, options).map_err(|err| match (err, options) {
+        (Errno::EINVAL, Some(options)) => {
+            format!("{} (check filesystem options: {})", err, options).into()
+        }
+        _ => Box::<dyn std::error::Error>::from(err),
+    })
+}
+
+/// Parse octal file mode like `0755`.
+fn parse_mode(mode: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| Box::from(format!("invalid mode: {}", mode)))
+}
+
 impl Mount {
+    /// Create missing target as an empty file if `file` is set or as a directory otherwise.
+    fn create_target(&self, file: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if file {
+            if let Some(parent) = self.target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.target)?;
+        } else {
+            fs::create_dir_all(&self.target)?;
+        }
+
+        let Some(create) = &self.create else {
+            return Ok(());
+        };
+        if let Some(mode) = &create.mode {
+            fs::set_permissions(&self.target, Permissions::from_mode(parse_mode(mode)?))?;
+        }
+        if create.uid.is_some() || create.gid.is_some() {
+            chown(
+                &self.target,
+                create.uid.map(Uid::from_raw),
+                create.gid.map(Gid::from_raw),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Perform the mount.
+    ///
+    /// Depending on flags this is one of following operations, optionally followed by change
+    /// of propagation type (`private`, `shared`, `slave` or `unbindable`):
+    /// - bind mount (`bind` or `rbind`), which is remounted if flags like `ro` are set;
+    /// - remount of existing mount (`remount`);
+    /// - propagation change only, if no filesystem type is set;
+    /// - mount of a filesystem or move of existing mount (`move`).
+    ///
+    /// `MS_REC` in `flags` makes the bind recursive, in `propagation` it makes the propagation
+    /// change recursive.
     pub fn mount(&self) -> Result<(), Box<dyn std::error::Error>> {
         log::info!(target: TARGET, "{}", self);
 
-        let inner = || -> Result<(), Box<dyn std::error::Error>> {
-            if !self.target.exists() {
-                fs::create_dir_all(&self.target)?;
+        let flags = self.flags;
+        let recursive = flags & MsFlags::MS_REC;
+
+        // Returns whether a new mount was created.
+        let inner = || -> Result<bool, Box<dyn std::error::Error>> {
+            let remount = flags.contains(MsFlags::MS_REMOUNT);
+            let propagation_only = !self.propagation.is_empty()
+                && self.fstype.is_none()
+                && !flags.intersects(MsFlags::MS_BIND | MsFlags::MS_MOVE);
+            let created = !remount && !propagation_only;
+
+            if remount {
+                mount_syscall(None, &self.target, None, flags, self.options.as_deref())?;
+            } else if created {
+                let source = self
+                    .source
+                    .as_deref()
+                    .map(|source| resolve_source(source, self.wait_timeout))
+                    .transpose()?;
+                if let (Some(source), Some(format)) = (&source, &self.format) {
+                    mkfs::format_if_empty(Path::new(source), format)?;
+                }
+                if !self.target.exists() {
+                    // Bind or move of a file needs a file as mount point.
+                    let file = flags.intersects(MsFlags::MS_BIND | MsFlags::MS_MOVE)
+                        && source
+                            .as_deref()
+                            .is_some_and(|source| fs::metadata(source).is_ok_and(|m| !m.is_dir()));
+                    self.create_target(file)?;
+                }
+                if flags.contains(MsFlags::MS_BIND) {
+                    mount_syscall(
+                        source.as_deref(),
+                        &self.target,
+                        None,
+                        MsFlags::MS_BIND | recursive,
+                        None,
+                    )?;
+                    // Flags of bind mount can be only changed by remounting it, including
+                    // every submount of recursive bind.
+                    let remount_flags = flags & BIND_REMOUNT_FLAGS;
+                    if !remount_flags.is_empty() {
+                        remount_bind(&self.target, remount_flags)?;
+                        if !recursive.is_empty() {
+                            for submount in submounts(&self.target)? {
+                                remount_bind(&submount, remount_flags)?;
+                            }
+                        }
+                    }
+                } else {
+                    mount_syscall(
+                        source.as_deref(),
+                        &self.target,
+                        self.fstype.as_deref(),
+                        flags,
+                        self.options.as_deref(),
+                    )?;
+                }
             }
-            nix::mount::mount(
-                self.source.as_deref(),
-                &self.target,
-                self.fstype.as_deref(),
-                self.flags,
-                self.options.as_deref(),
-            )?;
-            Ok(())
+
+            if !self.propagation.is_empty() {
+                mount_syscall(None, &self.target, None, self.propagation, None)?;
+            }
+            Ok(created)
         };
         let result = inner();
+        if let Ok(true) = result {
+            MOUNTED.lock().unwrap().push(Mounted {
+                target: self.target.clone(),
+                default: false,
+            });
+        }
 
         if let Err(err) = result {
             if self.required {
@@ -77,20 +392,55 @@ impl Mount {
 }
 
 impl TryFrom<&RuntimeMount> for Mount {
-    type Error = &'static str;
+    type Error = Box<dyn std::error::Error>;
 
-    fn try_from(value: &RuntimeMount) -> Result<Self, &'static str> {
+    /// `data` is passed to the filesystem as is. `mount(8)` options are only parsed in mounts of
+    /// MIA configuration.
+    ///
+    /// Propagation flags are applied by a separate call, with `MS_REC` if it is set.
+    fn try_from(value: &RuntimeMount) -> Result<Self, Self::Error> {
+        let bits = value.flags.unwrap_or(0);
+        let flags = MsFlags::from_bits(bits).ok_or("invalid mount flags")?;
+        let mut propagation = flags & PROPAGATION_FLAGS;
+        if !propagation.is_empty() {
+            propagation |= flags & MsFlags::MS_REC;
+        }
         Ok(Self {
             source: Some(value.source.clone()),
             target: PathBuf::from(value.target.clone()),
             fstype: value.fstype.clone(),
-            flags: if let Some(bits) = value.flags {
-                MsFlags::from_bits(bits).ok_or("invalid mount flags")?
-            } else {
-                MsFlags::empty()
-            },
+            flags: flags - PROPAGATION_FLAGS,
+            propagation,
             options: value.data.clone(),
             required: true, // All user mounts are considered required
+            wait_timeout: None,
+            format: None,
+            create: None,
+        })
+    }
+}
+
+impl TryFrom<&MountConfig> for Mount {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(value: &MountConfig) -> Result<Self, Self::Error> {
+        let options = mount_options::parse_optional(value.options.as_deref())?;
+        Ok(Self {
+            source: Some(value.source.clone()),
+            target: PathBuf::from(value.target.clone()),
+            fstype: value.fstype.clone().or_else(|| {
+                value
+                    .format
+                    .as_ref()
+                    .map(|format| format.fstype.to_string())
+            }),
+            flags: options.flags,
+            propagation: options.propagation,
+            options: options.data,
+            required: true,
+            wait_timeout: value.wait_timeout.map(Duration::from_secs),
+            format: value.format.clone(),
+            create: value.create.clone(),
         })
     }
 }
@@ -108,9 +458,13 @@ impl From<&ConstMount> for Mount {
             target: PathBuf::from(value.1),
             fstype: Some(value.2.to_string()),
             flags: value.3,
+            propagation: MsFlags::empty(),
             options: value.4.map(ToString::to_string),
-            required: false, // All default mounts are considered not required,
-                             // because they depend on kernel config
+            // All default mounts are considered not required, because they depend on kernel config
+            required: false,
+            wait_timeout: None,
+            format: None,
+            create: None,
         }
     }
 }
@@ -141,6 +495,15 @@ pub const DEFAULT_MOUNT_TABLE: &[ConstMount] = &[
             .union(MsFlags::MS_NODEV),
         None,
     ),
+    (
+        "cgroup2",
+        "/sys/fs/cgroup",
+        "cgroup2",
+        MsFlags::MS_NOSUID
+            .union(MsFlags::MS_NOEXEC)
+            .union(MsFlags::MS_NODEV),
+        Some("nsdelegate"),
+    ),
     (
         // Right now we build kernel with CONFIG_DEVTMPFS_MOUNT=y, so we don't need to mount /dev.
         // It will be automatically mounted by kernel. That's why we set this to not critical.
@@ -190,7 +553,183 @@ pub const DEFAULT_MOUNT_TABLE: &[ConstMount] = &[
 /// Mount default filesystems from [`DEFAULT_MOUNT_TABLE`].
 pub fn default_mounts() -> Result<(), Box<dyn std::error::Error>> {
     for entry in DEFAULT_MOUNT_TABLE {
+        let count = MOUNTED.lock().unwrap().len();
         Mount::from(entry).mount()?;
+        for mounted in &mut MOUNTED.lock().unwrap()[count..] {
+            mounted.default = true;
+        }
+    }
+    Ok(())
+}
+
+/// Forget filesystems mounted so far.
+///
+/// Called after the root was replaced, when previous mounts were moved into the new root or
+/// are no longer reachable.
+pub fn forget_mounted() {
+    MOUNTED.lock().unwrap().clear();
+}
+
+fn remount_read_only(target: &Path) {
+    if let Err(err) = nix::mount::mount(
+        None::<&str>,
+        target,
+        None::<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    ) {
+        log::error!(target: TARGET, "remounting {} read-only: {}", target.display(), err);
+    }
+}
+
+/// Unmount filesystem at `target` or remount it read-only if it is busy.
+fn unmount(target: &Path) {
+    log::info!(target: TARGET, "unmounting {}", target.display());
+    match nix::mount::umount(target) {
+        Ok(()) => {}
+        Err(Errno::EBUSY) => {
+            log::warn!(
+                target: TARGET,
+                "{} is busy, remounting read-only",
+                target.display()
+            );
+            remount_read_only(target);
+        }
+        Err(err) => log::error!(target: TARGET, "unmounting {}: {}", target.display(), err),
+    }
+}
+
+/// Unmount filesystems mounted by MIA in reverse order and remount root read-only.
+///
+/// Filesystems mounted by [`default_mounts`] are left mounted, but user filesystems over the
+/// same paths are not. Busy filesystems are remounted read-only instead. Errors are logged.
+pub fn unmount_all() {
+    let mounted = std::mem::take(&mut *MOUNTED.lock().unwrap());
+    for Mounted { target, default } in mounted.iter().rev() {
+        if *default {
+            continue;
+        }
+        // Submounts not mounted by MIA directly, e.g. of recursive bind mounts.
+        let submounts = submounts(target).unwrap_or_default();
+        for submount in submounts.iter().rev() {
+            unmount(submount);
+        }
+        unmount(target);
+    }
+    log::info!(target: TARGET, "remounting / read-only");
+    remount_read_only(Path::new("/"));
+}
+
+/// Directory where upper layer of the root overlay is mounted.
+const OVERLAY_DIR: &str = "/run/mia/overlay";
+
+/// Directory with writable layers of read-only directories, located on `/run` tmpfs.
+const WRITABLE_DIR: &str = "/run/mia/writable";
+
+/// Mount overlay of `lower` directory at `target`, with upper and work directories created
+/// in `layers`.
+fn mount_overlay(
+    lower: &str,
+    target: PathBuf,
+    layers: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let name = match lower.trim_matches('/') {
+        "" => "root".to_string(),
+        name => name.replace('/', "-"),
+    };
+    let upper = layers.join("upper").join(&name);
+    let work = layers.join("work").join(&name);
+    fs::create_dir_all(&upper)?;
+    fs::create_dir_all(&work)?;
+    Mount {
+        source: Some("overlay".to_string()),
+        target,
+        fstype: Some("overlay".to_string()),
+        flags: MsFlags::empty(),
+        propagation: MsFlags::empty(),
+        options: Some(format!(
+            "lowerdir={},upperdir={},workdir={}",
+            lower,
+            upper.display(),
+            work.display()
+        )),
+        required: true,
+        wait_timeout: None,
+        format: None,
+        create: None,
+    }
+    .mount()
+}
+
+/// Make directory `dir` writable if it is on read-only filesystem.
+///
+/// Writable layer on tmpfs is stacked over the directory, so changes are lost on shutdown.
+pub fn ensure_writable(dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !statvfs(dir)?.flags().contains(FsFlags::ST_RDONLY) {
+        return Ok(());
+    }
+    log::info!(target: TARGET, "{} is read-only, adding writable layer", dir);
+    mount_overlay(dir, PathBuf::from(dir), Path::new(WRITABLE_DIR))
+}
+
+/// Remount root filesystem read-only and stack writable layer described by `config` over it.
+///
+/// If `config.directories` is empty, the whole root is overlaid: MIA moves into the merged
+/// tree and mounts default filesystems in it again. Otherwise only listed directories are
+/// overlaid and the rest of the root stays read-only.
+pub fn overlay(config: &OverlayConfig) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!(target: TARGET, "remounting / read-only");
+    nix::mount::mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )?;
+
+    let overlay_dir = Path::new(OVERLAY_DIR);
+    let options = mount_options::parse_optional(config.options.as_deref())?;
+    Mount {
+        source: Some(config.source.clone().unwrap_or_else(|| "tmpfs".to_string())),
+        target: overlay_dir.to_path_buf(),
+        fstype: config
+            .fstype
+            .clone()
+            .or_else(|| config.source.is_none().then(|| "tmpfs".to_string())),
+        flags: options.flags,
+        propagation: options.propagation,
+        options: options.data,
+        required: true,
+        wait_timeout: None,
+        format: None,
+        create: None,
+    }
+    .mount()?;
+
+    let whole_root = config.directories.is_empty();
+    let directories = if whole_root {
+        vec!["/".to_string()]
+    } else {
+        config.directories.clone()
+    };
+    for dir in &directories {
+        let target = if whole_root {
+            overlay_dir.join("merged")
+        } else {
+            PathBuf::from(dir)
+        };
+        mount_overlay(dir, target, overlay_dir)?;
+    }
+
+    if whole_root {
+        log::info!(target: TARGET, "moving into overlaid root");
+        nix::unistd::chdir(&overlay_dir.join("merged"))?;
+        nix::mount::mount(Some("."), "/", None::<&str>, MsFlags::MS_MOVE, None::<&str>)?;
+        nix::unistd::chroot(".")?;
+        nix::unistd::chdir("/")?;
+        // Previous mounts are hidden under the new root.
+        forget_mounted();
+        default_mounts()?;
     }
     Ok(())
 }
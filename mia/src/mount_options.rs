@@ -0,0 +1,338 @@
+use std::fmt;
+
+use nix::mount::MsFlags;
+
+/// Group of options which are mutually exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Group {
+    ReadOnly,
+    Suid,
+    Dev,
+    Exec,
+    Sync,
+    DirSync,
+    Mand,
+    Atime,
+    DirAtime,
+    LazyTime,
+    Silent,
+    Operation,
+    Remount,
+    Propagation,
+}
+
+/// Generic mount option: name, group, flags to set and flags to clear.
+type GenericOption = (&'static str, Option<Group>, MsFlags, MsFlags);
+
+const NONE: MsFlags = MsFlags::empty();
+
+/// Filesystem-independent options, see `mount(8)`.
+const GENERIC_OPTIONS: &[GenericOption] = &[
+    ("defaults", None, NONE, NONE),
+    ("ro", Some(Group::ReadOnly), MsFlags::MS_RDONLY, NONE),
+    ("rw", Some(Group::ReadOnly), NONE, MsFlags::MS_RDONLY),
+    ("nosuid", Some(Group::Suid), MsFlags::MS_NOSUID, NONE),
+    ("suid", Some(Group::Suid), NONE, MsFlags::MS_NOSUID),
+    ("nodev", Some(Group::Dev), MsFlags::MS_NODEV, NONE),
+    ("dev", Some(Group::Dev), NONE, MsFlags::MS_NODEV),
+    ("noexec", Some(Group::Exec), MsFlags::MS_NOEXEC, NONE),
+    ("exec", Some(Group::Exec), NONE, MsFlags::MS_NOEXEC),
+    ("sync", Some(Group::Sync), MsFlags::MS_SYNCHRONOUS, NONE),
+    ("async", Some(Group::Sync), NONE, MsFlags::MS_SYNCHRONOUS),
+    ("dirsync", Some(Group::DirSync), MsFlags::MS_DIRSYNC, NONE),
+    ("mand", Some(Group::Mand), MsFlags::MS_MANDLOCK, NONE),
+    ("nomand", Some(Group::Mand), NONE, MsFlags::MS_MANDLOCK),
+    ("noatime", Some(Group::Atime), MsFlags::MS_NOATIME, NONE),
+    ("atime", Some(Group::Atime), NONE, MsFlags::MS_NOATIME),
+    ("relatime", Some(Group::Atime), MsFlags::MS_RELATIME, NONE),
+    ("norelatime", Some(Group::Atime), NONE, MsFlags::MS_RELATIME),
+    (
+        "strictatime",
+        Some(Group::Atime),
+        MsFlags::MS_STRICTATIME,
+        NONE,
+    ),
+    (
+        "nostrictatime",
+        Some(Group::Atime),
+        NONE,
+        MsFlags::MS_STRICTATIME,
+    ),
+    (
+        "nodiratime",
+        Some(Group::DirAtime),
+        MsFlags::MS_NODIRATIME,
+        NONE,
+    ),
+    (
+        "diratime",
+        Some(Group::DirAtime),
+        NONE,
+        MsFlags::MS_NODIRATIME,
+    ),
+    (
+        "lazytime",
+        Some(Group::LazyTime),
+        MsFlags::MS_LAZYTIME,
+        NONE,
+    ),
+    (
+        "nolazytime",
+        Some(Group::LazyTime),
+        NONE,
+        MsFlags::MS_LAZYTIME,
+    ),
+    ("silent", Some(Group::Silent), MsFlags::MS_SILENT, NONE),
+    ("loud", Some(Group::Silent), NONE, MsFlags::MS_SILENT),
+    ("remount", Some(Group::Remount), MsFlags::MS_REMOUNT, NONE),
+    ("bind", Some(Group::Operation), MsFlags::MS_BIND, NONE),
+    (
+        "rbind",
+        Some(Group::Operation),
+        MsFlags::MS_BIND.union(MsFlags::MS_REC),
+        NONE,
+    ),
+    ("move", Some(Group::Operation), MsFlags::MS_MOVE, NONE),
+    (
+        "private",
+        Some(Group::Propagation),
+        MsFlags::MS_PRIVATE,
+        NONE,
+    ),
+    (
+        "rprivate",
+        Some(Group::Propagation),
+        MsFlags::MS_PRIVATE.union(MsFlags::MS_REC),
+        NONE,
+    ),
+    ("shared", Some(Group::Propagation), MsFlags::MS_SHARED, NONE),
+    (
+        "rshared",
+        Some(Group::Propagation),
+        MsFlags::MS_SHARED.union(MsFlags::MS_REC),
+        NONE,
+    ),
+    ("slave", Some(Group::Propagation), MsFlags::MS_SLAVE, NONE),
+    (
+        "rslave",
+        Some(Group::Propagation),
+        MsFlags::MS_SLAVE.union(MsFlags::MS_REC),
+        NONE,
+    ),
+    (
+        "unbindable",
+        Some(Group::Propagation),
+        MsFlags::MS_UNBINDABLE,
+        NONE,
+    ),
+    (
+        "runbindable",
+        Some(Group::Propagation),
+        MsFlags::MS_UNBINDABLE.union(MsFlags::MS_REC),
+        NONE,
+    ),
+];
+
+/// Flags of operations other than propagation change which don't pass filesystem-specific data to the filesystem.
+const NO_DATA_FLAGS: MsFlags = MsFlags::MS_BIND.union(MsFlags::MS_MOVE);
+
+/// Error in mount options.
+#[derive(Debug)]
+pub enum OptionsError {
+    /// Option is not known and can't be passed to filesystem.
+    Unknown(String),
+
+    /// Two options are mutually exclusive.
+    Conflict(&'static str, &'static str),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(option) => write!(f, "unknown mount option: {}", option),
+            Self::Conflict(first, second) => {
+                write!(f, "conflicting mount options: {} and {}", first, second)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+/// Mount options split into the parts applied by separate `mount(2)` calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Mount flags. `MS_REC` makes a bind mount recursive.
+    pub flags: MsFlags,
+
+    /// Propagation type to set after mounting. `MS_REC` changes it in the whole subtree.
+    pub propagation: MsFlags,
+
+    /// Filesystem-specific data.
+    pub data: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            flags: MsFlags::empty(),
+            propagation: MsFlags::empty(),
+            data: None,
+        }
+    }
+}
+
+/// Split `mount(8)`-style option string like `ro,nosuid,size=10m` into mount flags,
+/// propagation type and filesystem-specific data.
+///
+/// Options which are not filesystem-independent are passed to the filesystem as data, unless
+/// the operation (bind, move or propagation change) doesn't accept data.
+pub fn parse(options: &str) -> Result<Options, OptionsError> {
+    let mut flags = MsFlags::empty();
+    let mut propagation = MsFlags::empty();
+    let mut data: Vec<&str> = Vec::new();
+    let mut seen: Vec<&GenericOption> = Vec::new();
+
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let Some(generic) = GENERIC_OPTIONS.iter().find(|(name, ..)| *name == option) else {
+            data.push(option);
+            continue;
+        };
+        let (name, group, set, clear) = generic;
+        if let Some(other) = seen.iter().find(|(other, other_group, ..)| {
+            other != name && group.is_some() && other_group == group
+        }) {
+            return Err(OptionsError::Conflict(other.0, name));
+        }
+        seen.push(generic);
+        if *group == Some(Group::Propagation) {
+            propagation = *set;
+        } else {
+            flags.remove(*clear);
+            flags.insert(*set);
+        }
+    }
+
+    if flags.intersects(NO_DATA_FLAGS) || !propagation.is_empty() {
+        if let Some(option) = data.first() {
+            return Err(OptionsError::Unknown(option.to_string()));
+        }
+    }
+    Ok(Options {
+        flags,
+        propagation,
+        data: (!data.is_empty()).then(|| data.join(",")),
+    })
+}
+
+/// Parse options which may not be set, see [`parse`].
+pub fn parse_optional(options: Option<&str>) -> Result<Options, OptionsError> {
+    options.map_or(Ok(Options::default()), parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_and_data() {
+        let options = parse("ro,nosuid,nodev,size=10m,mode=0755").unwrap();
+        assert_eq!(
+            options.flags,
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV
+        );
+        assert_eq!(options.propagation, MsFlags::empty());
+        assert_eq!(options.data.as_deref(), Some("size=10m,mode=0755"));
+    }
+
+    #[test]
+    fn empty_options() {
+        assert_eq!(parse("").unwrap(), Options::default());
+        assert_eq!(parse("defaults").unwrap(), Options::default());
+        assert_eq!(parse_optional(None).unwrap(), Options::default());
+    }
+
+    #[test]
+    fn clearing_options() {
+        assert_eq!(parse("rw,suid,dev,exec").unwrap(), Options::default());
+    }
+
+    #[test]
+    fn repeated_option() {
+        let options = parse("ro,ro").unwrap();
+        assert_eq!(options.flags, MsFlags::MS_RDONLY);
+        assert_eq!(options.data, None);
+    }
+
+    #[test]
+    fn conflicting_options() {
+        for (options, first, second) in [
+            ("ro,rw", "ro", "rw"),
+            ("nosuid,suid", "nosuid", "suid"),
+            ("relatime,noatime", "relatime", "noatime"),
+            ("bind,move", "bind", "move"),
+            ("private,rshared", "private", "rshared"),
+        ] {
+            match parse(options) {
+                Err(OptionsError::Conflict(a, b)) => assert_eq!((a, b), (first, second)),
+                result => panic!("{}: unexpected {:?}", options, result),
+            }
+        }
+    }
+
+    #[test]
+    fn operations() {
+        assert_eq!(
+            parse("rbind,ro").unwrap(),
+            Options {
+                flags: MsFlags::MS_BIND | MsFlags::MS_REC | MsFlags::MS_RDONLY,
+                ..Options::default()
+            }
+        );
+        assert_eq!(
+            parse("remount,ro,size=1g").unwrap(),
+            Options {
+                flags: MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                data: Some("size=1g".to_string()),
+                ..Options::default()
+            }
+        );
+        assert_eq!(
+            parse("rslave").unwrap(),
+            Options {
+                propagation: MsFlags::MS_SLAVE | MsFlags::MS_REC,
+                ..Options::default()
+            }
+        );
+    }
+
+    #[test]
+    fn separate_recursion() {
+        assert_eq!(
+            parse("bind,rprivate").unwrap(),
+            Options {
+                flags: MsFlags::MS_BIND,
+                propagation: MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+                data: None,
+            }
+        );
+        assert_eq!(
+            parse("rbind,private").unwrap(),
+            Options {
+                flags: MsFlags::MS_BIND | MsFlags::MS_REC,
+                propagation: MsFlags::MS_PRIVATE,
+                data: None,
+            }
+        );
+    }
+
+    #[test]
+    fn data_rejected_for_operations_without_data() {
+        for options in ["bind,size=10m", "move,foo", "private,bar"] {
+            match parse(options) {
+                Err(OptionsError::Unknown(_)) => {}
+                result => panic!("{}: unexpected {:?}", options, result),
+            }
+        }
+    }
+}
@@ -0,0 +1,567 @@
+use std::ffi::CString;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+use std::{fs, io};
+
+use nix::errno::Errno;
+
+use crate::dhcp::{self, Lease};
+use crate::mia_config::{HostConfig, InterfaceConfig, NetworkConfig, RouteConfig};
+use crate::mount;
+
+const TARGET: &str = "network";
+
+const LOOPBACK: &str = "lo";
+
+const ETC: &str = "/etc";
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+const HOSTS: &str = "/etc/hosts";
+
+const GENERATED_HEADER: &str = "# Generated by MIA\n";
+
+/// Size of netlink message header.
+const NLMSG_HDRLEN: usize = 16;
+
+/// Size of buffer for netlink replies.
+const RECV_BUFFER_SIZE: usize = 8192;
+
+/// Netlink attributes and messages are aligned to 4 bytes.
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Route netlink message payload: fixed header followed by attributes.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(header: &[u8]) -> Self {
+        Self(header.to_vec())
+    }
+
+    /// Append attribute of `kind` with `data`.
+    fn attr(mut self, kind: u16, data: &[u8]) -> Self {
+        let len = 4 + data.len();
+        self.0.extend((len as u16).to_ne_bytes());
+        self.0.extend(kind.to_ne_bytes());
+        self.0.extend(data);
+        self.0.resize(align(self.0.len()), 0);
+        self
+    }
+}
+
+/// Route netlink socket, see `rtnetlink(7)`.
+struct Netlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Netlink {
+    fn open() -> io::Result<Self> {
+        // SAFETY: arguments are valid, returned descriptor is checked.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a newly created descriptor owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: sockaddr_nl is plain data, zero is a valid value.
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // SAFETY: addr is a valid sockaddr_nl of the given size.
+        let ret = unsafe {
+            libc::connect(
+                fd.as_raw_fd(),
+                (&addr as *const libc::sockaddr_nl).cast(),
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Send request of `kind` and wait for kernel acknowledgement.
+    fn request(&mut self, kind: u16, flags: libc::c_int, message: Message) -> io::Result<()> {
+        self.seq += 1;
+        let flags = (flags | libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+        let mut buf = Vec::with_capacity(NLMSG_HDRLEN + message.0.len());
+        buf.extend(((NLMSG_HDRLEN + message.0.len()) as u32).to_ne_bytes());
+        buf.extend(kind.to_ne_bytes());
+        buf.extend(flags.to_ne_bytes());
+        buf.extend(self.seq.to_ne_bytes());
+        buf.extend(0u32.to_ne_bytes());
+        buf.extend(message.0);
+        // SAFETY: buf is valid for reads of its length.
+        if unsafe { libc::send(self.fd.as_raw_fd(), buf.as_ptr().cast(), buf.len(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0u8; RECV_BUFFER_SIZE];
+        loop {
+            // SAFETY: buf is valid for writes of its length.
+            let len =
+                unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut offset = 0;
+            while offset + NLMSG_HDRLEN <= len as usize {
+                let header = &buf[offset..];
+                let msg_len = u32::from_ne_bytes(header[0..4].try_into().unwrap()) as usize;
+                let msg_type = u16::from_ne_bytes(header[4..6].try_into().unwrap());
+                let seq = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+                if msg_type == libc::NLMSG_ERROR as u16 && seq == self.seq {
+                    // Error code is negative errno, zero for acknowledgement.
+                    let error = i32::from_ne_bytes(header[16..20].try_into().unwrap());
+                    return match error {
+                        0 => Ok(()),
+                        error => Err(io::Error::from_raw_os_error(-error)),
+                    };
+                }
+                if msg_len < NLMSG_HDRLEN {
+                    break;
+                }
+                offset += align(msg_len);
+            }
+        }
+    }
+
+    /// Bring interface `index` up and optionally set its MTU.
+    fn set_link_up(&mut self, index: u32, mtu: Option<u32>) -> io::Result<()> {
+        // struct ifinfomsg
+        let mut header = [0u8; 16];
+        header[0] = libc::AF_UNSPEC as u8;
+        header[4..8].copy_from_slice(&index.to_ne_bytes());
+        header[8..12].copy_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+        header[12..16].copy_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+        let mut message = Message::new(&header);
+        if let Some(mtu) = mtu {
+            message = message.attr(libc::IFLA_MTU, &mtu.to_ne_bytes());
+        }
+        self.request(libc::RTM_NEWLINK, 0, message)
+    }
+
+    /// Add `address` with `prefix_len` to interface `index`.
+    ///
+    /// With `lifetime` in seconds the kernel removes the address when it ends, otherwise the
+    /// address is permanent.
+    fn add_address(
+        &mut self,
+        index: u32,
+        address: IpAddr,
+        prefix_len: u8,
+        lifetime: Option<u32>,
+    ) -> io::Result<()> {
+        // struct ifaddrmsg
+        let mut header = [0u8; 8];
+        header[0] = family(address);
+        header[1] = prefix_len;
+        if address.is_ipv6() {
+            // Skip duplicate address detection, so that the address is usable immediately.
+            header[2] = libc::IFA_F_NODAD as u8;
+        }
+        header[4..8].copy_from_slice(&index.to_ne_bytes());
+        let octets = octets(address);
+        let mut message = Message::new(&header)
+            .attr(libc::IFA_LOCAL, &octets)
+            .attr(libc::IFA_ADDRESS, &octets);
+        if let Some(lifetime) = lifetime {
+            // struct ifa_cacheinfo: preferred and valid lifetime, timestamps are ignored.
+            let mut cacheinfo = [0u8; 16];
+            cacheinfo[0..4].copy_from_slice(&lifetime.to_ne_bytes());
+            cacheinfo[4..8].copy_from_slice(&lifetime.to_ne_bytes());
+            message = message.attr(libc::IFA_CACHEINFO, &cacheinfo);
+        }
+        self.request(
+            libc::RTM_NEWADDR,
+            libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+            message,
+        )
+    }
+
+    /// Add route to `destination` with `prefix_len` via interface `index`.
+    ///
+    /// Without `gateway`, the destination is reachable directly on the link.
+    fn add_route(
+        &mut self,
+        index: u32,
+        destination: IpAddr,
+        prefix_len: u8,
+        gateway: Option<IpAddr>,
+        metric: Option<u32>,
+    ) -> io::Result<()> {
+        // struct rtmsg
+        let mut header = [0u8; 12];
+        header[0] = family(destination);
+        header[1] = prefix_len;
+        header[4] = libc::RT_TABLE_MAIN;
+        header[5] = libc::RTPROT_BOOT;
+        header[6] = if gateway.is_some() {
+            libc::RT_SCOPE_UNIVERSE
+        } else {
+            libc::RT_SCOPE_LINK
+        };
+        header[7] = libc::RTN_UNICAST;
+        let mut message = Message::new(&header).attr(libc::RTA_OIF, &index.to_ne_bytes());
+        if prefix_len > 0 {
+            message = message.attr(libc::RTA_DST, &octets(destination));
+        }
+        if let Some(gateway) = gateway {
+            message = message.attr(libc::RTA_GATEWAY, &octets(gateway));
+        }
+        if let Some(metric) = metric {
+            message = message.attr(libc::RTA_PRIORITY, &metric.to_ne_bytes());
+        }
+        self.request(
+            libc::RTM_NEWROUTE,
+            libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+            message,
+        )
+    }
+}
+
+fn family(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn octets(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
+
+/// Parse address with optional prefix length like `10.0.0.2/24`.
+///
+/// Address without prefix length is a host address.
+fn parse_cidr(value: &str) -> Result<(IpAddr, u8), Box<dyn std::error::Error>> {
+    let invalid = || format!("invalid address: {}", value);
+    let (address, prefix_len) = match value.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (value, None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let max_len = if address.is_ipv4() { 32 } else { 128 };
+    let prefix_len = match prefix_len {
+        Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+        None => max_len,
+    };
+    if prefix_len > max_len {
+        return Err(Box::from(invalid()));
+    }
+    Ok((address, prefix_len))
+}
+
+fn interface_index(name: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    let c_name = CString::new(name)?;
+    // SAFETY: name is a valid null-terminated string.
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(Box::from(format!("interface {} not found", name))),
+        index => Ok(index),
+    }
+}
+
+/// Bring up loopback interface.
+pub fn loopback() -> Result<(), Box<dyn std::error::Error>> {
+    log::info!(target: TARGET, "bringing up {}", LOOPBACK);
+    Netlink::open()?
+        .set_link_up(interface_index(LOOPBACK)?, None)
+        .map_err(|err| Box::from(format!("bringing up {}: {}", LOOPBACK, err)))
+}
+
+fn add_route(
+    netlink: &mut Netlink,
+    index: u32,
+    route: &RouteConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = route
+        .gateway
+        .as_deref()
+        .map(|gateway| {
+            gateway
+                .parse::<IpAddr>()
+                .map_err(|_| format!("invalid gateway: {}", gateway))
+        })
+        .transpose()?;
+    let (destination, prefix_len) = if route.destination == "default" {
+        match gateway {
+            Some(IpAddr::V6(_)) => (IpAddr::from([0u16; 8]), 0),
+            _ => (IpAddr::from([0u8; 4]), 0),
+        }
+    } else {
+        parse_cidr(&route.destination)?
+    };
+    if gateway.is_some_and(|gateway| gateway.is_ipv4() != destination.is_ipv4()) {
+        return Err(Box::from(format!(
+            "gateway of route to {} has different address family",
+            route.destination
+        )));
+    }
+    netlink.add_route(index, destination, prefix_len, gateway, route.metric)?;
+    Ok(())
+}
+
+/// Write generated file `path` in `/etc`, adding writable layer over `/etc` if needed.
+///
+/// Symlinks like `/etc/resolv.conf -> /run/systemd/resolve/stub-resolv.conf` are replaced.
+fn write_etc(path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!(target: TARGET, "writing {}", path);
+    mount::ensure_writable(ETC)?;
+    let inner = || -> io::Result<()> {
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink()) {
+            fs::remove_file(path)?;
+        }
+        fs::write(path, content)
+    };
+    inner().map_err(|err| Box::from(format!("writing {}: {}", path, err)))
+}
+
+/// Write DNS `servers` and `search` domains to `/etc/resolv.conf`.
+fn write_resolv_conf(
+    servers: &[IpAddr],
+    search: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut content = String::from(GENERATED_HEADER);
+    if !search.is_empty() {
+        content.push_str(&format!("search {}\n", search.join(" ")));
+    }
+    for server in servers {
+        content.push_str(&format!("nameserver {}\n", server));
+    }
+    write_etc(RESOLV_CONF, &content)
+}
+
+/// Write `/etc/hosts` with localhost, own `hostname` and extra `hosts` entries.
+fn write_hosts(
+    hostname: Option<&str>,
+    domain: Option<&str>,
+    hosts: &[HostConfig],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut content = String::from(GENERATED_HEADER);
+    content.push_str("127.0.0.1\tlocalhost\n");
+    content.push_str("::1\tlocalhost ip6-localhost ip6-loopback\n");
+    match (hostname, domain) {
+        (Some(hostname), Some(domain)) => content.push_str(&format!(
+            "127.0.1.1\t{}.{} {}\n",
+            hostname, domain, hostname
+        )),
+        (Some(hostname), None) => content.push_str(&format!("127.0.1.1\t{}\n", hostname)),
+        _ => {}
+    }
+    for host in hosts {
+        let address: IpAddr = host
+            .address
+            .parse()
+            .map_err(|_| format!("invalid address: {}", host.address))?;
+        content.push_str(&format!("{}\t{}\n", address, host.names.join(" ")));
+    }
+    write_etc(HOSTS, &content)
+}
+
+/// Set kernel host name and NIS domain name.
+fn set_hostname(hostname: &str, domain: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!(target: TARGET, "hostname: {}", hostname);
+    // SAFETY: pointer and length describe valid string.
+    Errno::result(unsafe { libc::sethostname(hostname.as_ptr().cast(), hostname.len()) })
+        .map_err(|err| format!("setting hostname {}: {}", hostname, err))?;
+    if let Some(domain) = domain {
+        log::info!(target: TARGET, "domain name: {}", domain);
+        // SAFETY: pointer and length describe valid string.
+        Errno::result(unsafe { libc::setdomainname(domain.as_ptr().cast(), domain.len()) })
+            .map_err(|err| format!("setting domain name {}: {}", domain, err))?;
+    }
+    Ok(())
+}
+
+/// Configure address and default route from DHCP `lease`.
+///
+/// MIA doesn't renew leases, so the address is removed when the lease time ends.
+fn apply_lease(
+    netlink: &mut Netlink,
+    index: u32,
+    lease: &Lease,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let address = IpAddr::V4(lease.address);
+    netlink.add_address(index, address, lease.prefix_len, lease.lease_time)?;
+    if let Some(gateway) = lease.gateway {
+        // Gateway outside of the leased subnet, e.g. with /32 lease, is reachable on the link.
+        let mask = u32::MAX
+            .checked_shl(32 - lease.prefix_len as u32)
+            .unwrap_or(0);
+        if u32::from(gateway) & mask != u32::from(lease.address) & mask {
+            netlink.add_route(index, IpAddr::V4(gateway), 32, None, None)?;
+        }
+        netlink.add_route(
+            index,
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            0,
+            Some(IpAddr::V4(gateway)),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Configure interface, returning DHCP lease if it was requested.
+fn configure_interface(
+    netlink: &mut Netlink,
+    config: &InterfaceConfig,
+) -> Result<Option<Lease>, Box<dyn std::error::Error>> {
+    let index = interface_index(&config.name)?;
+    log::info!(target: TARGET, "bringing up {}", config.name);
+    netlink.set_link_up(index, config.mtu)?;
+    for address in &config.addresses {
+        log::info!(target: TARGET, "{}: address {}", config.name, address);
+        let (address, prefix_len) = parse_cidr(address)?;
+        netlink.add_address(index, address, prefix_len, None)?;
+    }
+    let mut lease = None;
+    if config.dhcp {
+        let timeout = config
+            .dhcp_timeout
+            .map_or(dhcp::DEFAULT_TIMEOUT, Duration::from_secs);
+        let dhcp_lease = dhcp::request(&config.name, timeout)?;
+        apply_lease(netlink, index, &dhcp_lease)?;
+        lease = Some(dhcp_lease);
+    }
+    for route in &config.routes {
+        log::info!(
+            target: TARGET,
+            "{}: route {} via {}",
+            config.name,
+            route.destination,
+            route.gateway.as_deref().unwrap_or("link")
+        );
+        add_route(netlink, index, route)?;
+    }
+    Ok(lease)
+}
+
+/// Configure network interfaces, host name and DNS.
+///
+/// `/etc/resolv.conf` lists configured DNS servers followed by ones received from DHCP.
+pub fn configure(config: &NetworkConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(hostname) = &config.hostname {
+        set_hostname(hostname, config.domain.as_deref())?;
+    }
+    if config.hostname.is_some() || !config.hosts.is_empty() {
+        write_hosts(
+            config.hostname.as_deref(),
+            config.domain.as_deref(),
+            &config.hosts,
+        )?;
+    }
+
+    let mut dns_servers = config
+        .dns_servers
+        .iter()
+        .map(|server| {
+            server
+                .parse::<IpAddr>()
+                .map_err(|_| format!("invalid DNS server: {}", server))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut dns_search = config.dns_search.clone();
+
+    let mut netlink = Netlink::open()?;
+    for interface in &config.interfaces {
+        let lease = configure_interface(&mut netlink, interface)
+            .map_err(|err| format!("configuring {}: {}", interface.name, err))?;
+        if let Some(lease) = lease {
+            for server in lease.dns {
+                if !dns_servers.contains(&IpAddr::V4(server)) {
+                    dns_servers.push(IpAddr::V4(server));
+                }
+            }
+            dns_search.extend(lease.domain.filter(|domain| !dns_search.contains(domain)));
+        }
+    }
+
+    if !dns_servers.is_empty() {
+        write_resolv_conf(&dns_servers, &dns_search)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr() {
+        assert_eq!(
+            parse_cidr("10.0.0.2/24").unwrap(),
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        );
+        assert_eq!(
+            parse_cidr("fd00::2/64").unwrap(),
+            ("fd00::2".parse().unwrap(), 64)
+        );
+    }
+
+    #[test]
+    fn cidr_default_prefix() {
+        assert_eq!(
+            parse_cidr("10.0.0.2").unwrap(),
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 32)
+        );
+        assert_eq!(
+            parse_cidr("fd00::2").unwrap(),
+            ("fd00::2".parse().unwrap(), 128)
+        );
+    }
+
+    #[test]
+    fn invalid_cidr() {
+        for value in [
+            "10.0.0.2/33",
+            "fd00::2/129",
+            "10.0.0.2/",
+            "10.0.0.2/-1",
+            "10.0.0.256/24",
+            "10.0.0/24",
+            "host/24",
+            "",
+        ] {
+            assert!(parse_cidr(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(align(0), 0);
+        assert_eq!(align(1), 4);
+        assert_eq!(align(4), 4);
+        assert_eq!(align(5), 8);
+        assert_eq!(align(16), 16);
+    }
+
+    #[test]
+    fn attributes() {
+        let message = Message::new(&[1, 2, 3, 4])
+            .attr(libc::IFA_LOCAL, &[10, 0, 0, 2])
+            .attr(libc::IFA_LABEL, b"eth0\0");
+        let mut expected = vec![1, 2, 3, 4];
+        expected.extend(8u16.to_ne_bytes());
+        expected.extend(libc::IFA_LOCAL.to_ne_bytes());
+        expected.extend([10, 0, 0, 2]);
+        // Length excludes padding of the data.
+        expected.extend(9u16.to_ne_bytes());
+        expected.extend(libc::IFA_LABEL.to_ne_bytes());
+        expected.extend(b"eth0\0\0\0\0");
+        assert_eq!(message.0, expected);
+    }
+}
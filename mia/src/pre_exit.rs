@@ -1,6 +1,14 @@
 use std::io::{self, Write};
+use std::process;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::signals;
 
 const TARGET: &str = "pre-exit";
 
@@ -21,3 +29,73 @@ pub fn flush() {
     // Give some time to flush stdout/stderr
     thread::sleep(FLUSHING_DELAY);
 }
+
+/// Shell started with `mia.debug_shell` kernel parameter.
+const DEBUG_SHELL: &str = "/bin/sh";
+
+/// Run interactive shell on console and wait for it to exit.
+pub fn debug_shell() {
+    log::info!(target: TARGET, "starting debug shell, exit it to shut down");
+    let mut command = process::Command::new(DEBUG_SHELL);
+    signals::unblock_on_exec(&mut command);
+    match command.spawn().and_then(|mut child| child.wait()) {
+        Ok(status) => log::info!(target: TARGET, "debug shell {}", status),
+        Err(err) => log::error!(target: TARGET, "starting {}: {}", DEBUG_SHELL, err),
+    }
+}
+
+/// Time given to processes to exit after `SIGKILL`.
+const KILL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reap children until there are none left or `timeout` passes.
+///
+/// Returns `true` if all children exited.
+fn wait_children(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) => {}
+            Ok(status) => {
+                log::debug!(target: TARGET, "reaped: {:?}", status);
+                continue;
+            }
+            Err(Errno::EINTR) => continue,
+            Err(Errno::ECHILD) => return true,
+            Err(err) => {
+                log::error!(target: TARGET, "waiting for processes: {}", err);
+                return false;
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        // Woken up by SIGCHLD.
+        let _ = signals::wait(Some(deadline - now));
+    }
+}
+
+/// Terminate all processes except MIA.
+///
+/// Processes are sent `SIGTERM` and killed with `SIGKILL` if they don't exit within
+/// `grace_period`.
+pub fn kill_processes(grace_period: Duration) {
+    for (signal, timeout) in [
+        (Signal::SIGTERM, grace_period),
+        (Signal::SIGKILL, KILL_TIMEOUT),
+    ] {
+        // PID -1 sends signal to all processes except init.
+        match kill(Pid::from_raw(-1), signal) {
+            Ok(()) => log::info!(target: TARGET, "sending {} to remaining processes", signal),
+            Err(Errno::ESRCH) => return,
+            Err(err) => {
+                log::error!(target: TARGET, "sending {}: {}", signal, err);
+                return;
+            }
+        }
+        if wait_children(timeout) {
+            return;
+        }
+    }
+    log::warn!(target: TARGET, "some processes did not exit");
+}
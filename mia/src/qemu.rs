@@ -2,11 +2,31 @@ use nix::errno::Errno;
 use once_cell::sync::OnceCell;
 use qemu_exit::{QEMUExit, X86};
 
+use crate::command::ExitStatus;
+
 const TARGET: &str = "qemu-debug-exit";
 
 /// QEMU exit handler.
 pub static QEMU_EXIT_HANDLER: OnceCell<X86> = OnceCell::new();
 
+/// Success code of QEMU exit handler.
+static SUCCESS_CODE: OnceCell<u32> = OnceCell::new();
+
+/// Base and size of debug exit I/O ports MIA has access to.
+static IO_PORTS: OnceCell<(u16, u64)> = OnceCell::new();
+
+/// Debug exit value reported when MIA failed without main command exit status.
+const MIA_FAILURE_VALUE: u32 = 0;
+
+/// Maximum exit code of the main command which can be reported exactly.
+const MAX_EXIT_CODE: i32 = 63;
+
+/// Base of debug exit values reporting a signal which terminated the main command.
+const SIGNAL_VALUE_BASE: u32 = 64;
+
+/// Debug exit value reported when a command timed out.
+const TIMEOUT_VALUE: u32 = SIGNAL_VALUE_BASE;
+
 /// Setup QEMU exit handler and grant the process permissions to write to debug port `iobase`.
 pub fn setup(
     iobase: u16,
@@ -30,14 +50,67 @@ pub fn setup(
         success_code
     );
     let _ = QEMU_EXIT_HANDLER.get_or_init(|| X86::new(iobase, success_code));
+    let _ = SUCCESS_CODE.get_or_init(|| success_code);
+    let _ = IO_PORTS.get_or_init(|| (iobase, iosize));
+    Ok(())
+}
+
+/// Revoke access to debug exit I/O ports, which is inherited by child processes.
+///
+/// Called in child processes which must not be able to exit QEMU. Only makes a syscall, so it
+/// is safe to call after `fork`.
+pub fn revoke_io_ports() -> std::io::Result<()> {
+    if let Some((iobase, iosize)) = IO_PORTS.get() {
+        // SAFETY: disabling port access has no memory effects.
+        if unsafe { libc::ioperm((*iobase).into(), *iosize, 0) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
     Ok(())
 }
 
-fn exit_error() {
+/// Encode `status` of the main command into debug exit value.
+///
+/// QEMU exits with code `(value << 1) | 1`, and only the lowest 8 bits of it are visible to the
+/// host. Values are assigned as follows:
+///
+/// | Value           | QEMU exit code   | Meaning                                            |
+/// |-----------------|------------------|----------------------------------------------------|
+/// | 0               | 1                | MIA failed, main command status is unknown         |
+/// | `code`          | 3..=127          | main command exited with `code` (1..=63)           |
+/// | 64              | 129              | main command or boot command timed out             |
+/// | `64 + signal`   | 131..=191        | main command was killed by `signal` (1..=31)       |
+///
+/// Exit codes 63..=255 can't be told apart: all of them are reported as 63 (QEMU exit code
+/// 127). The exact exit code is written to the status record, see `status-path` of MIA
+/// configuration. Success is reported with configured success code. If encoded value clashes
+/// with it, [`MIA_FAILURE_VALUE`] is used instead.
+fn exit_value(status: ExitStatus, success_code: u32) -> u32 {
+    let value = match status {
+        ExitStatus::Exited(code) => code.clamp(1, MAX_EXIT_CODE) as u32,
+        ExitStatus::Signaled(signal) => SIGNAL_VALUE_BASE + signal as u32,
+        ExitStatus::TimedOut => TIMEOUT_VALUE,
+    };
+    if value == success_code >> 1 {
+        log::warn!(
+            target: TARGET,
+            "exit value 0x{:x} clashes with success code, reporting generic error",
+            value
+        );
+        return MIA_FAILURE_VALUE;
+    }
+    value
+}
+
+fn exit_error(status: Option<ExitStatus>) {
     log::info!(target: TARGET, "exiting QEMU with error");
-    if let Some(handler) = QEMU_EXIT_HANDLER.get() {
+    if let (Some(handler), Some(success_code)) = (QEMU_EXIT_HANDLER.get(), SUCCESS_CODE.get()) {
+        let value = status.map_or(MIA_FAILURE_VALUE, |status| {
+            exit_value(status, *success_code)
+        });
+        log::info!(target: TARGET, "exit value 0x{:x}", value);
         crate::pre_exit::flush();
-        handler.exit_failure()
+        handler.exit(value)
     }
     log::error!(target: TARGET, "QEMU exit handler is not set");
 }
@@ -52,12 +125,69 @@ fn exit_success() {
 }
 
 /// Try exiting QEMU with debug code.
-/// `error` specifies whether exit with error or with success.
+/// `status` is the exit status of the main command or `None` if MIA failed before it finished.
+/// See [`exit_value`] for encoding of unsuccessful status.
 /// This functions returns only if exiting QEMU failed (exit handler is not set).
-pub fn exit(error: bool) {
-    if error {
-        exit_error()
-    } else {
-        exit_success()
+pub fn exit(status: Option<ExitStatus>) {
+    match status {
+        Some(status) if status.success() => exit_success(),
+        status => exit_error(status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nix::sys::signal::Signal;
+
+    /// Success code which doesn't clash with any exit value.
+    const SUCCESS: u32 = 0x0;
+
+    /// Exit code of QEMU process for debug exit `value`, as seen by the host.
+    fn qemu_exit_code(value: u32) -> u8 {
+        ((value << 1) | 1) as u8
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_value(ExitStatus::Exited(1), SUCCESS), 1);
+        assert_eq!(exit_value(ExitStatus::Exited(62), SUCCESS), 62);
+        assert_eq!(exit_value(ExitStatus::Exited(63), SUCCESS), 63);
+        assert_eq!(qemu_exit_code(1), 3);
+        assert_eq!(qemu_exit_code(63), 127);
+    }
+
+    #[test]
+    fn large_exit_codes_are_clamped() {
+        for code in [63, 126, 127, 255] {
+            assert_eq!(exit_value(ExitStatus::Exited(code), SUCCESS), 63);
+        }
+    }
+
+    #[test]
+    fn signals() {
+        let value = exit_value(ExitStatus::Signaled(Signal::SIGKILL), SUCCESS);
+        assert_eq!(value, 73);
+        assert_eq!(qemu_exit_code(value), 147);
+        assert_eq!(
+            qemu_exit_code(exit_value(ExitStatus::Signaled(Signal::SIGHUP), SUCCESS)),
+            131
+        );
+        assert_eq!(
+            qemu_exit_code(exit_value(ExitStatus::Signaled(Signal::SIGSYS), SUCCESS)),
+            191
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        assert_eq!(exit_value(ExitStatus::TimedOut, SUCCESS), 64);
+        assert_eq!(qemu_exit_code(64), 129);
+    }
+
+    #[test]
+    fn clash_with_success_code() {
+        assert_eq!(exit_value(ExitStatus::Exited(2), 0x5), MIA_FAILURE_VALUE);
     }
 }
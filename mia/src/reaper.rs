@@ -0,0 +1,48 @@
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::services;
+
+const TARGET: &str = "reaper";
+
+/// Reap all exited children without blocking.
+///
+/// Any other child than `pid` (e.g. orphaned process re-parented to MIA) is reaped as well,
+/// so that it doesn't stay in zombie state. Exited services are reported to [`services`].
+///
+/// Returns status of process `pid` if it was among them.
+pub fn reap(pid: Pid) -> Result<Option<WaitStatus>, Box<dyn std::error::Error>> {
+    reap_all(Some(pid))
+}
+
+/// Reap all exited children without blocking.
+pub fn reap_orphans() -> Result<(), Box<dyn std::error::Error>> {
+    reap_all(None).map(|_| ())
+}
+
+fn reap_all(pid: Option<Pid>) -> Result<Option<WaitStatus>, Box<dyn std::error::Error>> {
+    let mut result = None;
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) => break,
+            Ok(status) if pid.is_some() && status.pid() == pid => {
+                result = Some(status);
+            }
+            Ok(status) => {
+                if !services::exited(status) {
+                    log::debug!(target: TARGET, "reaped orphan: {:?}", status);
+                }
+            }
+            Err(Errno::EINTR) => continue,
+            Err(Errno::ECHILD) => {
+                if let (Some(pid), None) = (pid, result) {
+                    return Err(Box::from(format!("process {} is not a child of MIA", pid)));
+                }
+                break;
+            }
+            Err(err) => return Err(Box::new(err)),
+        }
+    }
+    Ok(result)
+}
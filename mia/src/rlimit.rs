@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::process;
+
+use nix::sys::resource::{setrlimit, Resource, RLIM_INFINITY};
+
+use crate::mia_config::{LimitValue, RlimitConfig};
+
+const TARGET: &str = "rlimit";
+
+/// Resources which can be limited, see `getrlimit(2)`.
+const RESOURCES: &[(&str, Resource)] = &[
+    ("as", Resource::RLIMIT_AS),
+    ("core", Resource::RLIMIT_CORE),
+    ("cpu", Resource::RLIMIT_CPU),
+    ("data", Resource::RLIMIT_DATA),
+    ("fsize", Resource::RLIMIT_FSIZE),
+    ("locks", Resource::RLIMIT_LOCKS),
+    ("memlock", Resource::RLIMIT_MEMLOCK),
+    ("msgqueue", Resource::RLIMIT_MSGQUEUE),
+    ("nice", Resource::RLIMIT_NICE),
+    ("nofile", Resource::RLIMIT_NOFILE),
+    ("nproc", Resource::RLIMIT_NPROC),
+    ("rss", Resource::RLIMIT_RSS),
+    ("rtprio", Resource::RLIMIT_RTPRIO),
+    ("rttime", Resource::RLIMIT_RTTIME),
+    ("sigpending", Resource::RLIMIT_SIGPENDING),
+    ("stack", Resource::RLIMIT_STACK),
+];
+
+/// Parse resource name like `RLIMIT_NOFILE` or `nofile`.
+fn parse_resource(name: &str) -> Result<Resource, Box<dyn std::error::Error>> {
+    let lowercase = name.to_lowercase();
+    let short = lowercase.strip_prefix("rlimit_").unwrap_or(&lowercase);
+    RESOURCES
+        .iter()
+        .find(|(resource, _)| *resource == short)
+        .map(|(_, resource)| *resource)
+        .ok_or_else(|| Box::from(format!("unknown resource limit: {}", name)))
+}
+
+fn parse_value(value: &LimitValue) -> Result<libc::rlim_t, Box<dyn std::error::Error>> {
+    match value {
+        LimitValue::Number(value) => Ok(*value),
+        LimitValue::Keyword(keyword) if keyword == "unlimited" || keyword == "infinity" => {
+            Ok(RLIM_INFINITY)
+        }
+        LimitValue::Keyword(keyword) => Err(Box::from(format!(
+            "invalid resource limit value: {}",
+            keyword
+        ))),
+    }
+}
+
+fn format_value(value: libc::rlim_t) -> String {
+    if value == RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Single resource limit.
+#[derive(Debug, Clone, Copy)]
+struct Rlimit {
+    resource: Resource,
+    soft: libc::rlim_t,
+    hard: libc::rlim_t,
+}
+
+/// Set of resource limits applied to a command.
+#[derive(Debug, Clone, Default)]
+pub struct Rlimits(Vec<Rlimit>);
+
+impl TryFrom<&BTreeMap<String, RlimitConfig>> for Rlimits {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(value: &BTreeMap<String, RlimitConfig>) -> Result<Self, Self::Error> {
+        value
+            .iter()
+            .map(|(name, limit)| {
+                let resource = parse_resource(name)?;
+                let (soft, hard) = match limit {
+                    RlimitConfig::Value(value) => {
+                        let value = parse_value(value)?;
+                        (value, value)
+                    }
+                    RlimitConfig::SoftHard { soft, hard } => {
+                        (parse_value(soft)?, parse_value(hard)?)
+                    }
+                };
+                if soft > hard {
+                    return Err(Box::from(format!(
+                        "soft limit of {} is greater than hard limit",
+                        name
+                    )));
+                }
+                Ok(Rlimit {
+                    resource,
+                    soft,
+                    hard,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Rlimits {
+    /// Set resource limits in the child process before executing `command`.
+    pub fn apply_on_exec(&self, command: &mut process::Command) {
+        if self.0.is_empty() {
+            return;
+        }
+        for limit in &self.0 {
+            log::info!(
+                target: TARGET,
+                "{:?}: soft={} hard={}",
+                limit.resource,
+                format_value(limit.soft),
+                format_value(limit.hard)
+            );
+        }
+        let limits = self.0.clone();
+        // SAFETY: `setrlimit` is async-signal-safe, no memory is allocated.
+        unsafe {
+            command.pre_exec(move || {
+                for limit in &limits {
+                    setrlimit(limit.resource, limit.soft, limit.hard)?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
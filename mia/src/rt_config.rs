@@ -1,23 +1,92 @@
-use gevulot_rs::runtime_config::{self, DebugExit, RuntimeConfig};
+use std::time::{Duration, Instant};
 
-use crate::command::Command;
+use gevulot_rs::runtime_config::{self, DebugExit, EnvVar, RuntimeConfig};
+
+use crate::cgroup::Cgroup;
+use crate::cmdline;
+use crate::command::{self, Command};
+use crate::mia_config::MiaConfig;
 use crate::modprobe::Modprobe;
-use crate::mount::Mount;
+use crate::mount::{self, Mount};
+use crate::network;
 use crate::qemu;
+use crate::rlimit::Rlimits;
+use crate::status::{self, Phase};
+use crate::swap;
+use crate::switch_root::switch_root;
+use crate::sysctl;
+use crate::user::Credentials;
 
 const TARGET: &str = "rt-config";
 
-pub fn load(mut path: String) -> Result<Command, Box<dyn std::error::Error>> {
+/// Result of runtime configuration processing.
+pub struct Config {
+    /// Main command to run.
+    pub command: Command,
+
+    /// MIA configuration merged from all processed files.
+    pub mia: MiaConfig,
+}
+
+/// Apply command settings from MIA config.
+fn configure(mut command: Command, mia_config: &MiaConfig) -> Command {
+    if let Some(grace_period) = mia_config.grace_period {
+        command = command.grace_period(Duration::from_secs(grace_period));
+    }
+    command
+}
+
+/// Process runtime configuration starting from file `path`.
+///
+/// `start_time` is the time when MIA started, global deadline is counted from it.
+pub fn load(mut path: String, start_time: Instant) -> Result<Config, Box<dyn std::error::Error>> {
     let mut cmd: Option<Command> = None;
-    let modprobe = Modprobe::init()?;
+    let mut mia_config = MiaConfig::default();
+    // Initialized on first use, because initramfs may not contain modprobe.
+    let mut modprobe: Option<Modprobe> = None;
 
     log::info!(target: TARGET, "version {}", runtime_config::VERSION);
 
     loop {
+        status::set_phase(Phase::Config);
         log::info!(target: TARGET, "loading {}", &path);
 
         let config_file = std::fs::File::open(&path)?;
-        let config: RuntimeConfig = serde_yaml::from_reader(config_file)?;
+        let mut config: serde_yaml::Value = serde_yaml::from_reader(config_file)?;
+        let mut file_mia_config = MiaConfig::extract(&mut config)?;
+        let mut config: RuntimeConfig = serde_yaml::from_value(config)?;
+        for (key, value) in &cmdline::get().env {
+            match config.env.iter_mut().find(|env| &env.key == key) {
+                Some(env) => env.value = value.clone(),
+                None => config.env.push(EnvVar {
+                    key: key.clone(),
+                    value: value.clone(),
+                }),
+            }
+        }
+
+        if let Some(status_path) = &file_mia_config.status_path {
+            status::set_path(status_path.into());
+        }
+        // Timeout from kernel command line is set at startup and takes precedence.
+        if let Some(timeout) = file_mia_config
+            .timeout
+            .filter(|_| cmdline::get().timeout.is_none())
+        {
+            log::info!(target: TARGET, "global timeout: {}s", timeout);
+            command::set_deadline(start_time + Duration::from_secs(timeout));
+        }
+        let overlay = file_mia_config.overlay.take();
+        let mounts = std::mem::take(&mut file_mia_config.mounts);
+        let swaps = std::mem::take(&mut file_mia_config.swap);
+        let zram = file_mia_config.zram.take();
+        let sysctl_config = std::mem::take(&mut file_mia_config.sysctl);
+        let network_config = file_mia_config.network.take();
+        let switch_root_config = file_mia_config.switch_root.take();
+        mia_config.merge(file_mia_config);
+        if let Some(grace_period) = mia_config.grace_period {
+            command::set_grace_period(Duration::from_secs(grace_period));
+        }
 
         if let Some(DebugExit::X86 {
             iobase,
@@ -28,8 +97,20 @@ pub fn load(mut path: String) -> Result<Command, Box<dyn std::error::Error>> {
             qemu::setup(*iobase, *iosize as u64, *success_code)?;
         }
 
+        status::set_phase(Phase::Mount);
+        if let Some(overlay) = &overlay {
+            mount::overlay(overlay)?;
+        }
+        let wait_timeout = mia_config.mount_wait_timeout.map(Duration::from_secs);
         for mount in &config.mounts {
-            Mount::try_from(mount)?.mount()?;
+            let mut mount = Mount::try_from(mount)?;
+            mount.wait_timeout = wait_timeout;
+            mount.mount()?;
+        }
+        for mount in &mounts {
+            let mut mount = Mount::try_from(mount)?;
+            mount.wait_timeout = mount.wait_timeout.or(wait_timeout);
+            mount.mount()?;
         }
 
         for env in &config.env {
@@ -42,22 +123,56 @@ pub fn load(mut path: String) -> Result<Command, Box<dyn std::error::Error>> {
             log::info!(target: TARGET, "working dir set: {}", working_dir);
         }
 
+        status::set_phase(Phase::Modprobe);
         for module in &config.kernel_modules {
-            modprobe.load(module)?;
+            if modprobe.is_none() {
+                modprobe = Some(Modprobe::init()?);
+            }
+            modprobe.as_ref().unwrap().load(module)?;
+        }
+
+        status::set_phase(Phase::Sysctl);
+        sysctl::apply(&sysctl_config)?;
+
+        if let Some(network_config) = &network_config {
+            status::set_phase(Phase::Network);
+            network::configure(network_config)?;
         }
 
+        status::set_phase(Phase::Swap);
+        if let Some(zram) = &zram {
+            swap::zram(zram)?;
+        }
+        for swap in &swaps {
+            swap::activate(swap, wait_timeout)?;
+        }
+
+        status::set_phase(Phase::Bootcmd);
         for cmd in &config.bootcmd {
             log::info!(target: TARGET, "bootcmd: {}", cmd.join(" "));
             if cmd.is_empty() {
                 return Err(Box::from("no command to run found"));
             }
-            Command::new(cmd[0].clone(), cmd[1..].to_vec()).run()?;
+            let mut bootcmd =
+                configure(Command::new(cmd[0].clone(), cmd[1..].to_vec()), &mia_config);
+            if let Some(timeout) = mia_config.bootcmd_timeout {
+                bootcmd = bootcmd.timeout(Duration::from_secs(timeout));
+            }
+            bootcmd = bootcmd.rlimits(Rlimits::try_from(&mia_config.bootcmd_rlimits)?);
+            bootcmd.run()?;
         }
 
         if let Some(command) = &config.command {
             cmd = Some(Command::new(command.clone(), config.args.clone()));
         }
 
+        if let Some(switch_root_config) = &switch_root_config {
+            status::set_phase(Phase::Mount);
+            switch_root(switch_root_config)?;
+            // Modprobe has to be looked up in the new root.
+            modprobe = None;
+        }
+
         if let Some(follow_config) = &config.follow_config {
             path = follow_config.clone();
         } else {
@@ -70,5 +185,23 @@ pub fn load(mut path: String) -> Result<Command, Box<dyn std::error::Error>> {
         return Err(Box::from("no command to run found"));
     }
 
-    Ok(cmd.unwrap())
+    let mut cmd = configure(cmd.unwrap(), &mia_config);
+    if let Some(restart) = &mia_config.restart {
+        cmd = cmd.restart(restart.clone());
+    }
+    if let Some(timeout) = mia_config.command_timeout {
+        cmd = cmd.timeout(Duration::from_secs(timeout));
+    }
+    cmd = cmd.rlimits(Rlimits::try_from(&mia_config.command_rlimits)?);
+    if let Some(cgroup) = &mia_config.cgroup {
+        cmd = cmd.cgroup(Cgroup::create(cgroup)?);
+    }
+    if let Some(user) = &mia_config.user {
+        cmd = cmd.credentials(Credentials::try_from(user)?);
+    }
+
+    Ok(Config {
+        command: cmd,
+        mia: mia_config,
+    })
 }
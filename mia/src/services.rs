@@ -0,0 +1,190 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+
+use crate::command::{send_signal, Command, ExitStatus};
+use crate::mia_config::{RestartConfig, ServiceConfig};
+use crate::{reaper, signals};
+
+const TARGET: &str = "services";
+
+/// Background service running alongside the main command.
+struct Service {
+    name: String,
+    command: Command,
+    restart: RestartConfig,
+    /// PID of running service process.
+    pid: Option<Pid>,
+    /// Number of times the service was started.
+    attempts: u32,
+    /// Delay before next restart.
+    backoff: Duration,
+    /// Time when exited service should be restarted.
+    restart_at: Option<Instant>,
+}
+
+/// Services started by MIA in order of start.
+static SERVICES: Mutex<Vec<Service>> = Mutex::new(Vec::new());
+
+/// Set when services are being stopped, so that they are not restarted anymore.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Start services in order of specification.
+pub fn start(configs: &[ServiceConfig]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut services = SERVICES.lock().unwrap();
+    for config in configs {
+        log::info!(target: TARGET, "starting {}", &config.name);
+        let command = Command::new(config.command.clone(), config.args.clone());
+        let pid = command.spawn()?;
+        let restart = config.restart.clone().unwrap_or_default();
+        services.push(Service {
+            name: config.name.clone(),
+            command,
+            backoff: Duration::from_secs(restart.backoff),
+            restart,
+            pid: Some(pid),
+            attempts: 1,
+            restart_at: None,
+        });
+    }
+    Ok(())
+}
+
+/// Handle exit of a child process.
+///
+/// Returns `false` if the process is not a service.
+pub fn exited(status: WaitStatus) -> bool {
+    let mut services = SERVICES.lock().unwrap();
+    let Some(service) = services
+        .iter_mut()
+        .find(|service| service.pid.is_some() && service.pid == status.pid())
+    else {
+        return false;
+    };
+    service.pid = None;
+    let Some(status) = ExitStatus::from_wait_status(status) else {
+        log::warn!(target: TARGET, "{}: unexpected status {:?}", &service.name, status);
+        return true;
+    };
+
+    let reason = if STOPPING.load(Ordering::SeqCst) {
+        Some("services are stopping")
+    } else {
+        service.restart.stop_reason(status, service.attempts)
+    };
+    if let Some(reason) = reason {
+        log::warn!(
+            target: TARGET,
+            "{} {}, not restarting: {}",
+            &service.name,
+            status,
+            reason
+        );
+    } else {
+        log::warn!(
+            target: TARGET,
+            "{} {}, restarting in {:?}",
+            &service.name,
+            status,
+            service.backoff
+        );
+        service.restart_at = Some(Instant::now() + service.backoff);
+        service.backoff =
+            (service.backoff * 2).min(Duration::from_secs(service.restart.max_backoff));
+    }
+    true
+}
+
+/// Earliest time when some service should be restarted.
+pub fn next_restart() -> Option<Instant> {
+    SERVICES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|service| service.restart_at)
+        .min()
+}
+
+/// Restart services whose restart time has come.
+pub fn restart_due() {
+    let now = Instant::now();
+    let mut services = SERVICES.lock().unwrap();
+    for service in services
+        .iter_mut()
+        .filter(|service| service.restart_at.is_some_and(|time| time <= now))
+    {
+        service.restart_at = None;
+        service.attempts += 1;
+        log::info!(
+            target: TARGET,
+            "restarting {} (attempt {})",
+            &service.name,
+            service.attempts
+        );
+        match service.command.spawn() {
+            Ok(pid) => service.pid = Some(pid),
+            Err(err) => log::error!(target: TARGET, "{}: {}", &service.name, err),
+        }
+    }
+}
+
+/// Stop services in reverse order.
+///
+/// Each service is sent `SIGTERM` and killed with `SIGKILL` if it doesn't exit
+/// within `grace_period`.
+pub fn stop(grace_period: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    STOPPING.store(true, Ordering::SeqCst);
+    let count = SERVICES.lock().unwrap().len();
+    for index in (0..count).rev() {
+        // Lock is released before waiting, because exits of other services are reported to
+        // `exited()` by reaper.
+        let (name, pid) = {
+            let mut services = SERVICES.lock().unwrap();
+            let service = &mut services[index];
+            service.restart_at = None;
+            match service.pid {
+                Some(pid) => (service.name.clone(), pid),
+                None => continue,
+            }
+        };
+        log::info!(target: TARGET, "stopping {}", &name);
+        send_signal(pid, Signal::SIGTERM);
+        let status = wait(pid, grace_period, &name)?;
+        SERVICES.lock().unwrap()[index].pid = None;
+        match ExitStatus::from_wait_status(status) {
+            Some(status) => log::info!(target: TARGET, "{} {}", &name, status),
+            None => log::warn!(target: TARGET, "{}: unexpected status {:?}", &name, status),
+        }
+    }
+    Ok(())
+}
+
+/// Wait for service process `pid` to exit, killing it after `grace_period`.
+fn wait(
+    pid: Pid,
+    grace_period: Duration,
+    name: &str,
+) -> Result<WaitStatus, Box<dyn std::error::Error>> {
+    let deadline = Instant::now() + grace_period;
+    let mut killed = false;
+    loop {
+        if let Some(status) = reaper::reap(pid)? {
+            return Ok(status);
+        }
+        let timeout = (!killed).then(|| deadline.saturating_duration_since(Instant::now()));
+        if signals::wait(timeout)?.is_none() && !killed && Instant::now() >= deadline {
+            log::warn!(
+                target: TARGET,
+                "{} did not exit within {:?}, killing",
+                name,
+                grace_period
+            );
+            send_signal(pid, Signal::SIGKILL);
+            killed = true;
+        }
+    }
+}
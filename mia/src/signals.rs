@@ -0,0 +1,92 @@
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::signal::{SigSet, Signal};
+
+const TARGET: &str = "signals";
+
+/// Signals requesting termination of the workload.
+///
+/// `SIGINT` is also sent by kernel on Ctrl-Alt-Del once it is disabled in [`setup`].
+const TERMINATION_SIGNALS: &[Signal] = &[Signal::SIGTERM, Signal::SIGINT, Signal::SIGPWR];
+
+/// Set once any of [`TERMINATION_SIGNALS`] was received.
+static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn handled_signals() -> SigSet {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGCHLD);
+    for signal in TERMINATION_SIGNALS {
+        mask.add(*signal);
+    }
+    mask
+}
+
+/// Block handled signals so that they are only received synchronously in [`wait`].
+///
+/// Must be called before spawning any threads, because signal mask is inherited by them.
+/// Child processes inherit it as well, so it must be reset with [`unblock_on_exec`].
+pub fn setup() -> Result<(), Box<dyn std::error::Error>> {
+    log::debug!(target: TARGET, "block SIGCHLD, SIGTERM, SIGINT, SIGPWR");
+    handled_signals().thread_block()?;
+
+    // Make kernel send SIGINT to init on Ctrl-Alt-Del instead of rebooting immediately.
+    if let Err(err) = nix::sys::reboot::set_cad_enabled(false) {
+        log::warn!(target: TARGET, "disabling Ctrl-Alt-Del: {}", err);
+    }
+    Ok(())
+}
+
+/// Unblock all signals in the child process before executing `command`.
+pub fn unblock_on_exec(command: &mut process::Command) {
+    // SAFETY: `sigprocmask` is async-signal-safe, so it can be called after `fork`.
+    unsafe {
+        command.pre_exec(|| {
+            SigSet::empty().thread_set_mask()?;
+            Ok(())
+        });
+    }
+}
+
+/// Wait for one of handled signals.
+///
+/// If `timeout` is set and no signal was received before it expires, returns `None`.
+pub fn wait(timeout: Option<Duration>) -> Result<Option<Signal>, Box<dyn std::error::Error>> {
+    let mask = handled_signals();
+    let signal = match timeout {
+        None => mask.wait()?,
+        Some(timeout) => {
+            let timeout = libc::timespec {
+                tv_sec: timeout.as_secs() as libc::time_t,
+                tv_nsec: timeout.subsec_nanos() as libc::c_long,
+            };
+            let ret = unsafe { libc::sigtimedwait(mask.as_ref(), std::ptr::null_mut(), &timeout) };
+            if ret < 0 {
+                return match Errno::last() {
+                    Errno::EAGAIN | Errno::EINTR => Ok(None),
+                    err => Err(Box::new(err)),
+                };
+            }
+            Signal::try_from(ret)?
+        }
+    };
+    if TERMINATION_SIGNALS.contains(&signal) {
+        log::warn!(target: TARGET, "received {}", signal);
+        TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+    }
+    Ok(Some(signal))
+}
+
+/// Consume all pending signals without blocking.
+pub fn poll() -> Result<(), Box<dyn std::error::Error>> {
+    while wait(Some(Duration::ZERO))?.is_some() {}
+    Ok(())
+}
+
+/// Whether termination of the workload was requested with a signal.
+pub fn termination_requested() -> bool {
+    TERMINATION_REQUESTED.load(Ordering::SeqCst)
+}
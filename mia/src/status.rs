@@ -0,0 +1,184 @@
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use nix::sys::resource::{getrusage, Usage, UsageWho};
+use nix::sys::time::TimeVal;
+use serde::Serialize;
+
+use crate::command::ExitStatus;
+
+const TARGET: &str = "status";
+
+/// Phase of MIA execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Mounting filesystems.
+    Mount,
+
+    /// Loading runtime configuration.
+    Config,
+
+    /// Loading kernel modules.
+    Modprobe,
+
+    /// Setting kernel parameters.
+    Sysctl,
+
+    /// Configuring network.
+    Network,
+
+    /// Activating swap.
+    Swap,
+
+    /// Running boot commands.
+    Bootcmd,
+
+    /// Starting services.
+    Services,
+
+    /// Running the main command.
+    Command,
+}
+
+/// Machine-readable result of MIA execution.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StatusRecord {
+    /// Whether the main command finished successfully.
+    pub success: bool,
+
+    /// Exit code of the main command, if it exited normally.
+    ///
+    /// Unlike QEMU debug exit, which reports all codes from 63 up as 63, this is exact.
+    pub exit_code: Option<i32>,
+
+    /// Signal which terminated the main command.
+    pub signal: Option<&'static str>,
+
+    /// Whether the main command or a boot command timed out.
+    pub timed_out: bool,
+
+    /// Phase of MIA execution which failed.
+    pub failed_phase: Option<Phase>,
+
+    /// Error message.
+    pub error: Option<String>,
+
+    /// Wall time of the main command in seconds.
+    pub wall_time: f64,
+
+    /// CPU time spent by the main command in user mode in seconds.
+    pub user_time: f64,
+
+    /// CPU time spent by the main command in kernel mode in seconds.
+    pub system_time: f64,
+
+    /// Maximum resident set size among all waited children in kilobytes.
+    pub max_rss: i64,
+
+    /// Number of processes of the main command killed by OOM killer.
+    ///
+    /// Only available if the main command runs in a cgroup with memory limits.
+    pub oom_kills: Option<u64>,
+}
+
+struct State {
+    phase: Phase,
+    path: Option<PathBuf>,
+    /// Start time and children resource usage at the beginning of [`Phase::Command`].
+    command_start: Option<(Instant, Usage)>,
+    oom_kills: Option<u64>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    phase: Phase::Mount,
+    path: None,
+    command_start: None,
+    oom_kills: None,
+});
+
+/// Set path to write status record to.
+///
+/// This can be a regular file (e.g. on output mount) or a device like a serial port.
+pub fn set_path(path: PathBuf) {
+    log::info!(target: TARGET, "status record: {}", path.display());
+    STATE.lock().unwrap().path = Some(path);
+}
+
+/// Set number of OOM kills in the cgroup of the main command.
+pub fn set_oom_kills(oom_kills: u64) {
+    STATE.lock().unwrap().oom_kills = Some(oom_kills);
+}
+
+/// Set current phase of MIA execution.
+pub fn set_phase(phase: Phase) {
+    log::debug!(target: TARGET, "phase: {:?}", phase);
+    let mut state = STATE.lock().unwrap();
+    state.phase = phase;
+    if phase == Phase::Command && state.command_start.is_none() {
+        if let Ok(usage) = getrusage(UsageWho::RUSAGE_CHILDREN) {
+            state.command_start = Some((Instant::now(), usage));
+        }
+    }
+}
+
+fn seconds(time: TimeVal) -> f64 {
+    time.tv_sec() as f64 + time.tv_usec() as f64 / 1_000_000.0
+}
+
+/// Write status record if path was set.
+///
+/// `status` is the exit status of the main command or `None` if MIA failed before it finished.
+pub fn write(status: Option<ExitStatus>, error: Option<String>) {
+    let state = STATE.lock().unwrap();
+    let Some(path) = &state.path else {
+        return;
+    };
+
+    let success = status.is_some_and(|status| status.success());
+    let mut record = StatusRecord {
+        success,
+        exit_code: None,
+        signal: None,
+        timed_out: false,
+        failed_phase: (!success).then_some(state.phase),
+        error,
+        wall_time: 0.0,
+        user_time: 0.0,
+        system_time: 0.0,
+        max_rss: 0,
+        oom_kills: state.oom_kills,
+    };
+    match status {
+        Some(ExitStatus::Exited(code)) => record.exit_code = Some(code),
+        Some(ExitStatus::Signaled(signal)) => record.signal = Some(signal.as_str()),
+        Some(ExitStatus::TimedOut) => record.timed_out = true,
+        None => {}
+    }
+    if let (Some((start_time, start_usage)), Ok(usage)) =
+        (&state.command_start, getrusage(UsageWho::RUSAGE_CHILDREN))
+    {
+        record.wall_time = start_time.elapsed().as_secs_f64();
+        record.user_time = seconds(usage.user_time() - start_usage.user_time());
+        record.system_time = seconds(usage.system_time() - start_usage.system_time());
+        record.max_rss = usage.max_rss();
+    }
+
+    log::info!(target: TARGET, "writing status record to {}", path.display());
+    if let Err(err) = write_record(path, &record) {
+        log::error!(target: TARGET, "writing status record: {}", err);
+    }
+}
+
+fn write_record(path: &Path, record: &StatusRecord) -> Result<(), Box<dyn std::error::Error>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    serde_yaml::to_writer(file, record)?;
+    Ok(())
+}
@@ -0,0 +1,333 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::fcntl::{fallocate, FallocateFlags};
+
+use crate::block;
+use crate::mia_config::{SwapConfig, ZramConfig};
+use crate::mount;
+
+const TARGET: &str = "swap";
+
+/// Signature at the end of the first page of swap space.
+const SWAP_SIGNATURE: &[u8] = b"SWAPSPACE2";
+
+/// Offset of swap header after boot block.
+const SWAP_HEADER: usize = 1024;
+
+/// Minimum number of pages in swap space accepted by the kernel.
+const MIN_SWAP_PAGES: u64 = 10;
+
+// Flags of `swapon(2)`, not exported by libc.
+const SWAP_FLAG_PREFER: libc::c_int = 0x8000;
+const SWAP_FLAG_PRIO_MASK: libc::c_int = 0x7fff;
+const SWAP_FLAG_DISCARD: libc::c_int = 0x10000;
+
+/// Control directory of zram, exists if zram module is loaded.
+const ZRAM_CONTROL: &str = "/sys/class/zram-control";
+
+const SYS_BLOCK: &str = "/sys/block";
+
+/// Swap priority of zram, so that it is used before disk swap.
+const DEFAULT_ZRAM_PRIORITY: u16 = 100;
+
+/// Swap spaces activated by MIA.
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Parse size like `512M` or `4G` into bytes. Suffixes are powers of 1024.
+fn parse_size(size: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let size = size.trim();
+    let (number, shift) = match size.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let shift = match suffix.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(Box::from(format!("invalid size: {}", size))),
+            };
+            (&size[..index], shift)
+        }
+        _ => (size, 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| Box::from(format!("invalid size: {}", size)))
+}
+
+fn page_size() -> u64 {
+    // SAFETY: sysconf has no side effects.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+/// Write swap header to `path` of `size` bytes, like `mkswap(8)`.
+fn write_header(path: &Path, size: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let page_size = page_size();
+    let pages = size / page_size;
+    if pages < MIN_SWAP_PAGES {
+        return Err(Box::from(format!(
+            "{} is too small for swap: {} bytes",
+            path.display(),
+            size
+        )));
+    }
+    let mut uuid = [0; 16];
+    File::open("/dev/urandom")?.read_exact_at(&mut uuid, 0)?;
+    // Random UUID version 4, variant 1.
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+
+    let mut page = vec![0; page_size as usize];
+    let header = &mut page[SWAP_HEADER..];
+    // version
+    header[0..4].copy_from_slice(&1u32.to_le_bytes());
+    // last_page
+    header[4..8].copy_from_slice(&((pages - 1) as u32).to_le_bytes());
+    // nr_badpages is zero, followed by UUID
+    header[12..28].copy_from_slice(&uuid);
+    let signature = page.len() - SWAP_SIGNATURE.len();
+    page[signature..].copy_from_slice(SWAP_SIGNATURE);
+
+    log::info!(target: TARGET, "creating swap header on {}", path.display());
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.write_all_at(&page, 0)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Size of device or regular file `path` in bytes.
+fn size(path: &Path) -> io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_file() {
+        return Ok(metadata.len());
+    }
+    // Size of block device can only be found by seeking to its end.
+    File::open(path)?.seek(SeekFrom::End(0))
+}
+
+/// Create swap header on `path` unless it already contains one.
+///
+/// Header is only written to a file `created` by MIA or to a blank device. Paths containing
+/// anything else, including filesystems not recognized by [`block::probe`], are never touched.
+fn prepare(path: &Path, created: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match block::probe(path)? {
+        Some(fs) if fs.fstype == "swap" => {
+            log::debug!(target: TARGET, "{} already contains swap", path.display());
+            Ok(())
+        }
+        Some(fs) => Err(Box::from(format!(
+            "{} contains {}, not using it as swap",
+            path.display(),
+            fs.fstype
+        ))),
+        None if block::has_partition_table(path)? => Err(Box::from(format!(
+            "{} contains partition table, not using it as swap",
+            path.display()
+        ))),
+        None if created || block::is_blank(path)? => write_header(path, size(path)?),
+        None => Err(Box::from(format!(
+            "{} contains unrecognized data, not using it as swap",
+            path.display()
+        ))),
+    }
+}
+
+/// Create swap file `path` of `size` bytes.
+///
+/// The space is allocated upfront, because the kernel rejects swap files with holes.
+fn create_file(path: &Path, size: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let size = parse_size(size)?;
+    log::info!(target: TARGET, "creating swap file {} of {} bytes", path.display(), size);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    let size = size - size % page_size();
+    if let Err(err) = fallocate(
+        file.as_raw_fd(),
+        FallocateFlags::empty(),
+        0,
+        size as libc::off_t,
+    ) {
+        let _ = fs::remove_file(path);
+        return Err(Box::from(format!(
+            "allocating swap file {}: {}",
+            path.display(),
+            err
+        )));
+    }
+    Ok(())
+}
+
+/// Enable swapping to `path`.
+fn swapon(
+    path: &Path,
+    priority: Option<u16>,
+    discard: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut flags = 0;
+    if let Some(priority) = priority {
+        flags |= SWAP_FLAG_PREFER | (priority as libc::c_int & SWAP_FLAG_PRIO_MASK);
+    }
+    if discard {
+        flags |= SWAP_FLAG_DISCARD;
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: path is a valid null-terminated string.
+    Errno::result(unsafe { libc::swapon(c_path.as_ptr(), flags) })
+        .map_err(|err| format!("swapon {}: {}", path.display(), err))?;
+    log::info!(target: TARGET, "swap enabled on {}", path.display());
+    ACTIVE.lock().unwrap().push(path.to_path_buf());
+    Ok(())
+}
+
+/// Activate swap device or file described by `config`.
+///
+/// Missing swap file is created if `size` is configured. Swap header is created on the new
+/// file or on a blank device.
+pub fn activate(
+    config: &SwapConfig,
+    wait_timeout: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut created = false;
+    let path = if config.source.starts_with('/') && !config.source.starts_with("/dev/") {
+        let path = PathBuf::from(&config.source);
+        if !path.exists() {
+            let Some(size) = &config.size else {
+                return Err(Box::from(format!(
+                    "swap file {} doesn't exist and size is not set",
+                    path.display()
+                )));
+            };
+            create_file(&path, size)?;
+            created = true;
+        }
+        path
+    } else {
+        let wait_timeout = config
+            .wait_timeout
+            .map(Duration::from_secs)
+            .or(wait_timeout);
+        PathBuf::from(mount::resolve_source(&config.source, wait_timeout)?)
+    };
+    prepare(&path, created)?;
+    swapon(&path, config.priority, config.discard)
+}
+
+/// Find unused zram device or add a new one.
+fn zram_device() -> Result<String, Box<dyn std::error::Error>> {
+    let mut names: Vec<String> = fs::read_dir(SYS_BLOCK)?
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("zram"))
+        .collect();
+    names.sort();
+    for name in names {
+        let disksize = fs::read_to_string(Path::new(SYS_BLOCK).join(&name).join("disksize"))?;
+        if disksize.trim() == "0" {
+            return Ok(name);
+        }
+    }
+    let id = fs::read_to_string(Path::new(ZRAM_CONTROL).join("hot_add"))?;
+    Ok(format!("zram{}", id.trim()))
+}
+
+/// Set up zram device described by `config` and use it as swap.
+///
+/// `zram` module has to be loaded, e.g. with `kernel-modules` of runtime configuration.
+pub fn zram(config: &ZramConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if !Path::new(ZRAM_CONTROL).exists() {
+        return Err(Box::from("zram is not available, load zram kernel module"));
+    }
+    let size = parse_size(&config.size)?;
+    let name = zram_device()?;
+    let sys_path = Path::new(SYS_BLOCK).join(&name);
+    log::info!(target: TARGET, "setting up {} of {} bytes", name, size);
+    if let Some(algorithm) = &config.algorithm {
+        fs::write(sys_path.join("comp_algorithm"), algorithm).map_err(|err| {
+            format!(
+                "setting {} compression algorithm {}: {}",
+                name, algorithm, err
+            )
+        })?;
+    }
+    fs::write(sys_path.join("disksize"), size.to_string())
+        .map_err(|err| format!("setting {} size: {}", name, err))?;
+
+    let device = Path::new("/dev").join(&name);
+    write_header(&device, size)?;
+    swapon(
+        &device,
+        Some(config.priority.unwrap_or(DEFAULT_ZRAM_PRIORITY)),
+        false,
+    )
+}
+
+fn swapoff(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: path is a valid null-terminated string.
+    Errno::result(unsafe { libc::swapoff(c_path.as_ptr()) })?;
+    Ok(())
+}
+
+/// Disable all swap spaces activated by MIA in reverse order.
+///
+/// Errors are logged and ignored.
+pub fn deactivate_all() {
+    let active = std::mem::take(&mut *ACTIVE.lock().unwrap());
+    for path in active.iter().rev() {
+        log::info!(target: TARGET, "disabling swap on {}", path.display());
+        if let Err(err) = swapoff(path) {
+            log::warn!(target: TARGET, "disabling swap on {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("1K").unwrap(), 1 << 10);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("8G").unwrap(), 8 << 30);
+        assert_eq!(parse_size("2T").unwrap(), 2 << 40);
+        assert_eq!(parse_size(" 4G ").unwrap(), 4 << 30);
+    }
+
+    #[test]
+    fn suffix_case() {
+        assert_eq!(parse_size("512m").unwrap(), parse_size("512M").unwrap());
+        assert_eq!(parse_size("8g").unwrap(), parse_size("8G").unwrap());
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for size in ["", "G", "-1G", "1.5G", "4X", "4GB", "G4", "abc"] {
+            assert!(parse_size(size).is_err(), "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(parse_size("16777215T").unwrap(), 16777215 << 40);
+        assert!(parse_size("16777216T").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::mount::{umount2, MntFlags, MsFlags};
+use nix::sys::signal::SigSet;
+use nix::sys::statfs::{statfs, FsType, TMPFS_MAGIC};
+use nix::unistd::{chdir, chroot, execv};
+
+use crate::mia_config::SwitchRootConfig;
+use crate::mount::{self, Mount};
+use crate::mount_options;
+
+const TARGET: &str = "switch-root";
+
+/// Mount point of the new root filesystem.
+const NEW_ROOT: &str = "/sysroot";
+
+/// Filesystems mounted by MIA which are moved into the new root.
+const MOVED_MOUNTS: &[&str] = &["/dev", "/proc", "/sys", "/run", "/tmp"];
+
+/// Magic number of ramfs, used for initramfs if tmpfs is not available.
+const RAMFS_MAGIC: u32 = 0x858458f6;
+
+/// Move mount at `path` into `new_root`.
+///
+/// Nothing is done if `path` is not a mount point, e.g. because its default mount failed.
+/// If the mount can't be moved, it is detached, so that it doesn't stay attached to the
+/// initramfs.
+fn move_mount(path: &Path, new_root: &Path) {
+    if !mount::is_mount_point(path) {
+        log::debug!(target: TARGET, "{} is not mounted, not moving it", path.display());
+        return;
+    }
+    let target = new_root.join(path.strip_prefix("/").unwrap_or(path));
+    log::debug!(target: TARGET, "moving {} to {}", path.display(), target.display());
+    let inner = || -> Result<(), Box<dyn std::error::Error>> {
+        if !target.exists() {
+            fs::create_dir_all(&target)?;
+        }
+        nix::mount::mount(
+            Some(path),
+            &target,
+            None::<&str>,
+            MsFlags::MS_MOVE,
+            None::<&str>,
+        )?;
+        Ok(())
+    };
+    if let Err(err) = inner() {
+        log::warn!(target: TARGET, "moving {}: {}", path.display(), err);
+        if let Err(err) = umount2(path, MntFlags::MNT_DETACH) {
+            log::debug!(target: TARGET, "detaching {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Recursively delete contents of directory `path` without crossing filesystem boundaries.
+///
+/// `dev` is the device of the filesystem being cleaned. Errors are logged and ignored.
+fn delete_contents(path: &Path, dev: u64) {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!(target: TARGET, "reading {}: {}", path.display(), err);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        // Skip mount points, including the new root.
+        if metadata.dev() != dev {
+            continue;
+        }
+        let result = if metadata.is_dir() {
+            delete_contents(&path, dev);
+            fs::remove_dir(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        if let Err(err) = result {
+            log::debug!(target: TARGET, "deleting {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Switch from initramfs to the root filesystem described by `config`.
+///
+/// This follows `switch_root(8)`: the new root is mounted, kernel API filesystems are moved
+/// into it, initramfs contents are deleted to free memory and the new root is moved over `/`.
+/// If `init` is configured, it is executed in place of MIA, otherwise this function returns
+/// and MIA continues in the new root. MIA also continues if `init` can't be executed, because
+/// the initramfs is already gone at that point.
+pub fn switch_root(config: &SwitchRootConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let root_type = statfs("/")?.filesystem_type();
+    if root_type != TMPFS_MAGIC && root_type != FsType(RAMFS_MAGIC as _) {
+        return Err(Box::from("root filesystem is not initramfs"));
+    }
+
+    let new_root = PathBuf::from(NEW_ROOT);
+    log::info!(target: TARGET, "switching root to {}", config.source);
+    let options = mount_options::parse_optional(config.options.as_deref())?;
+    Mount {
+        source: Some(config.source.clone()),
+        target: new_root.clone(),
+        fstype: config.fstype.clone(),
+        flags: options.flags,
+        propagation: options.propagation,
+        options: options.data,
+        required: true,
+        wait_timeout: config.wait_timeout.map(Duration::from_secs),
+        format: None,
+        create: None,
+    }
+    .mount()?;
+
+    for path in MOVED_MOUNTS {
+        move_mount(Path::new(path), &new_root);
+    }
+
+    chdir(&new_root)?;
+    log::info!(target: TARGET, "deleting initramfs contents");
+    delete_contents(Path::new("/"), fs::symlink_metadata("/")?.dev());
+
+    nix::mount::mount(Some("."), "/", None::<&str>, MsFlags::MS_MOVE, None::<&str>)?;
+    chroot(".")?;
+    chdir("/")?;
+    mount::forget_mounted();
+
+    if let Some(init) = &config.init {
+        log::info!(target: TARGET, "executing {}", init);
+        let path = CString::new(init.as_str())?;
+        // Signals blocked by MIA would stay blocked in the new init.
+        let mask = SigSet::thread_get_mask()?;
+        SigSet::empty().thread_set_mask()?;
+        let Err(err) = execv(&path, &[&path]);
+        mask.thread_set_mask()?;
+        log::error!(
+            target: TARGET,
+            "executing {}: {}, continuing with MIA in the new root",
+            init,
+            err
+        );
+    }
+    Ok(())
+}
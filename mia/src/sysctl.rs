@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::mia_config::{SysctlConfig, SysctlValue};
+
+const TARGET: &str = "sysctl";
+
+const PROC_SYS: &str = "/proc/sys";
+
+/// Path of parameter `key` like `vm.overcommit_memory` or `net/ipv4/conf/eth0.100/forwarding`.
+///
+/// As in `sysctl(8)`, dots are separators unless the key contains slashes.
+fn parameter_path(key: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let separator = if key.contains('/') { '/' } else { '.' };
+    let components: Vec<&str> = key.split(separator).collect();
+    if components
+        .iter()
+        .any(|component| component.is_empty() || *component == "..")
+    {
+        return Err(Box::from(format!("invalid sysctl key: {}", key)));
+    }
+    Ok(components
+        .iter()
+        .fold(PathBuf::from(PROC_SYS), |path, component| {
+            path.join(component)
+        }))
+}
+
+/// Read current value of parameter at `path` for logging.
+fn read_value(path: &Path) -> String {
+    // Some parameters like vm.drop_caches are write-only.
+    fs::read_to_string(path).map_or_else(|_| "?".to_string(), |value| value.trim().to_string())
+}
+
+fn set(key: &str, value: &SysctlValue) -> Result<(), Box<dyn std::error::Error>> {
+    let path = parameter_path(key)?;
+    let old = read_value(&path);
+    fs::write(&path, value.to_string())?;
+    log::info!(target: TARGET, "{}: {} -> {}", key, old, read_value(&path));
+    Ok(())
+}
+
+/// Set kernel parameters in `settings`.
+///
+/// Failures of parameters which are not required are logged and ignored.
+pub fn apply(settings: &BTreeMap<String, SysctlConfig>) -> Result<(), Box<dyn std::error::Error>> {
+    for (key, config) in settings {
+        let (value, required) = match config {
+            SysctlConfig::Value(value) => (value, true),
+            SysctlConfig::Full { value, required } => (value, *required),
+        };
+        if let Err(err) = set(key, value) {
+            if required {
+                return Err(Box::from(format!("setting {} to {}: {}", key, value, err)));
+            }
+            log::warn!(target: TARGET, "setting {} to {}: {}", key, value, err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dotted_key() {
+        assert_eq!(
+            parameter_path("vm.overcommit_memory").unwrap(),
+            Path::new("/proc/sys/vm/overcommit_memory")
+        );
+    }
+
+    #[test]
+    fn slashed_key() {
+        // Dots are part of the name if the key contains slashes, e.g. VLAN interfaces.
+        assert_eq!(
+            parameter_path("net/ipv4/conf/eth0.100/forwarding").unwrap(),
+            Path::new("/proc/sys/net/ipv4/conf/eth0.100/forwarding")
+        );
+    }
+
+    #[test]
+    fn invalid_keys() {
+        for key in [
+            "",
+            "vm..overcommit_memory",
+            ".vm.swappiness",
+            "vm.swappiness.",
+            "net/../vm/swappiness",
+            "../../etc/passwd",
+            "/vm/swappiness",
+            "vm/swappiness/",
+        ] {
+            assert!(parameter_path(key).is_err(), "{:?}", key);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process;
+
+use nix::unistd::{setgroups, setresgid, setresuid, Gid, Uid};
+
+use crate::mia_config::UserConfig;
+use crate::qemu;
+
+const TARGET: &str = "user";
+
+/// Linux capabilities in order of their numbers.
+///
+/// See `capabilities(7)`.
+const CAPABILITIES: &[&str] = &[
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+const CAP_LAST_CAP_PATH: &str = "/proc/sys/kernel/cap_last_cap";
+
+/// `_LINUX_CAPABILITY_VERSION_3` of `capset(2)`, with capability sets split into two words.
+const CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+/// `struct __user_cap_header_struct` of `capset(2)`.
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// `struct __user_cap_data_struct` of `capset(2)`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Parse capability name like `CAP_NET_ADMIN` or `net_admin` into its number.
+fn parse_capability(name: &str) -> Result<libc::c_ulong, Box<dyn std::error::Error>> {
+    let lowercase = name.to_lowercase();
+    let short = lowercase.strip_prefix("cap_").unwrap_or(&lowercase);
+    CAPABILITIES
+        .iter()
+        .position(|cap| *cap == short)
+        .map(|cap| cap as libc::c_ulong)
+        .ok_or_else(|| Box::from(format!("unknown capability: {}", name)))
+}
+
+/// Last capability supported by the kernel.
+fn last_capability() -> libc::c_ulong {
+    fs::read_to_string(CAP_LAST_CAP_PATH)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(CAPABILITIES.len() as libc::c_ulong - 1)
+}
+
+/// Credentials and privileges of a command.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    uid: Uid,
+    gid: Gid,
+    groups: Vec<Gid>,
+    /// Capabilities to drop from bounding set.
+    drop_capabilities: Vec<libc::c_ulong>,
+    /// Capabilities to raise in ambient set, so that they are kept by non-root users.
+    ambient_capabilities: Vec<libc::c_ulong>,
+    /// Capability sets containing [`Self::ambient_capabilities`].
+    ambient_sets: [CapData; 2],
+}
+
+impl TryFrom<&UserConfig> for Credentials {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(value: &UserConfig) -> Result<Self, Self::Error> {
+        let (drop_capabilities, ambient_capabilities) = match &value.capabilities {
+            Some(names) => {
+                let keep = names
+                    .iter()
+                    .map(|name| parse_capability(name))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let drop = (0..=last_capability())
+                    .filter(|cap| !keep.contains(cap))
+                    .collect();
+                (drop, keep)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        let mut ambient_sets = [CapData::default(); 2];
+        for cap in &ambient_capabilities {
+            let data = &mut ambient_sets[*cap as usize / 32];
+            let bit = 1 << (cap % 32);
+            data.effective |= bit;
+            data.permitted |= bit;
+            data.inheritable |= bit;
+        }
+        Ok(Self {
+            uid: Uid::from_raw(value.uid),
+            gid: Gid::from_raw(value.gid.unwrap_or(value.uid)),
+            groups: value.groups.iter().copied().map(Gid::from_raw).collect(),
+            drop_capabilities,
+            ambient_capabilities,
+            ambient_sets,
+        })
+    }
+}
+
+impl Credentials {
+    /// Apply credentials in the child process before executing `command`.
+    ///
+    /// Access to QEMU debug exit port is revoked first, so that the command can't fake VM exit.
+    /// Capabilities are dropped from bounding set before changing user, because it requires
+    /// `CAP_SETPCAP`. Kept capabilities survive the user change with `PR_SET_KEEPCAPS` and are
+    /// raised in ambient set, so that the command gets them even as non-root user. Finally
+    /// `PR_SET_NO_NEW_PRIVS` is set, so that the command can't gain privileges with
+    /// set-user-ID binaries or file capabilities.
+    pub fn apply_on_exec(&self, command: &mut process::Command) {
+        log::info!(
+            target: TARGET,
+            "uid={} gid={} groups=[{}] dropped capabilities: {} ambient capabilities: {}",
+            self.uid,
+            self.gid,
+            self.groups
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            self.drop_capabilities.len(),
+            self.ambient_capabilities.len()
+        );
+        let credentials = self.clone();
+        // SAFETY: only async-signal-safe syscalls are made, no memory is allocated.
+        unsafe {
+            command.pre_exec(move || {
+                qemu::revoke_io_ports()?;
+                setgroups(&credentials.groups)?;
+                setresgid(credentials.gid, credentials.gid, credentials.gid)?;
+                for cap in &credentials.drop_capabilities {
+                    if libc::prctl(libc::PR_CAPBSET_DROP, *cap, 0, 0, 0) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                let keep_capabilities = !credentials.ambient_capabilities.is_empty();
+                if keep_capabilities && libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                setresuid(credentials.uid, credentials.uid, credentials.uid)?;
+                if keep_capabilities {
+                    // Capabilities can be raised in ambient set only if they are both permitted
+                    // and inheritable.
+                    let mut header = CapHeader {
+                        version: CAPABILITY_VERSION_3,
+                        pid: 0,
+                    };
+                    let ret = libc::syscall(
+                        libc::SYS_capset,
+                        &mut header as *mut CapHeader,
+                        credentials.ambient_sets.as_ptr(),
+                    );
+                    if ret != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    for cap in &credentials.ambient_capabilities {
+                        if libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, *cap, 0, 0)
+                            != 0
+                        {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                }
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}